@@ -0,0 +1,171 @@
+// Status bar output. --status --bar <FORMAT> prints one line of status for a menu or status bar, eg
+// "🔒 42m left" while blocking or "next block 14:00", in the format the bar runs its commands with:
+//   waybar: a custom module's json (text, tooltip, class, alt)
+//   i3blocks: a block for a format=json blocklet, i3blocks then speaks the i3bar protocol to i3bar
+//   swiftbar or xbar: a plugin's output, the title then a menu under ---
+// Bars run it every few seconds, so SelfControl's state comes from a cache (see
+// begin_block::cached_selfcontrol_block_end) rather than running print-settings each time.
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use super::{begin_block, config::Config, pause, skips, ResultE};
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Waybar,
+    I3blocks,
+    Swiftbar,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> ResultE<Self> {
+        match name {
+            "waybar" => Ok(Format::Waybar),
+            "i3blocks" => Ok(Format::I3blocks),
+            "swiftbar" | "xbar" => Ok(Format::Swiftbar),
+            _ => Err(format!("unknown bar format: {}, expected waybar, i3blocks, swiftbar or xbar", name).into()),
+        }
+    }
+}
+
+enum State {
+    Blocking { until: NaiveDateTime },
+    // a scheduled block is active, but SelfControl isn't running (yet)
+    NotStarted { until: NaiveDateTime },
+    Skipped,
+    Paused,
+    Idle,
+}
+
+impl State {
+    // the class for styling, in waybar's css or swiftbar's
+    fn class(&self) -> &'static str {
+        match self {
+            State::Blocking { .. } => "blocking",
+            State::NotStarted { .. } => "not-started",
+            State::Skipped => "skipped",
+            State::Paused => "paused",
+            State::Idle => "idle",
+        }
+    }
+}
+
+pub struct Status {
+    state: State,
+    text: String,
+    short_text: String,
+    // the lines of the tooltip or menu
+    details: Vec<String>,
+}
+
+pub fn status(config: &Config, now: NaiveDateTime) -> ResultE<Status> {
+    let selfcontrol_end = begin_block::cached_selfcontrol_block_end(config, now)?;
+    let active_block = config.get_active_block_start(now).zip(config.get_active_block_end(now));
+    let state = match (selfcontrol_end, active_block) {
+        (Some(until), _) if until > now => State::Blocking { until },
+        (_, Some((start, _))) if skips::is_skipped(start)? => State::Skipped,
+        (_, Some(_)) if pause::is_paused(now)? => State::Paused,
+        (_, Some((_, until))) => State::NotStarted { until },
+        _ => State::Idle,
+    };
+    let next_block = skips::next_block(config, now)?;
+
+    let next_block_start = |start: NaiveDateTime| match start.date() == now.date() {
+        true => start.format("%H:%M").to_string(),
+        false => start.format("%a %H:%M").to_string(),
+    };
+    // (text, short text) for bars short of space
+    let (text, short_text) = match (&state, next_block) {
+        (State::Blocking { until }, _) => {
+            let remaining = remaining(*until - now);
+            (format!("🔒 {} left", remaining), format!("🔒 {}", remaining))
+        }
+        (State::NotStarted { until }, _) => (
+            format!("🔓 block until {} not started", until.format("%H:%M")),
+            "🔓".to_string(),
+        ),
+        (State::Skipped, _) => ("block skipped".to_string(), "skipped".to_string()),
+        (State::Paused, _) => ("blocks paused".to_string(), "paused".to_string()),
+        (State::Idle, Some((start, _))) => {
+            let start = next_block_start(start);
+            (format!("next block {}", start), start)
+        }
+        (State::Idle, None) => ("no blocks".to_string(), "-".to_string()),
+    };
+
+    let mut details = vec![];
+    if let State::Blocking { until } = state {
+        details.push(format!("Blocking until {}", until.format("%a %H:%M")));
+    }
+    match next_block {
+        Some((start, end)) => details.push(format!(
+            "Next block: {} until {}",
+            next_block_start(start),
+            end.format("%H:%M")
+        )),
+        None => details.push("Next block: none in the next week".to_string()),
+    }
+    details.push(format!(
+        "Skips left this week: {} of {}",
+        skips::skips_left(config, now)?,
+        config.skips_per_week
+    ));
+
+    Ok(Status {
+        state,
+        text,
+        short_text,
+        details,
+    })
+}
+
+// eg 42m or 1h 05m, rounded up so it doesn't show 0m while still blocking
+fn remaining(duration: chrono::Duration) -> String {
+    let minutes = (duration.num_seconds() + 59) / 60;
+    match minutes < 60 {
+        true => format!("{}m", minutes),
+        false => format!("{}h {:02}m", minutes / 60, minutes % 60),
+    }
+}
+
+#[derive(Serialize)]
+struct WaybarOutput<'a> {
+    text: &'a str,
+    tooltip: String,
+    class: &'a str,
+    alt: &'a str,
+}
+
+#[derive(Serialize)]
+struct I3blocksBlock<'a> {
+    name: &'a str,
+    full_text: &'a str,
+    short_text: &'a str,
+    urgent: bool,
+}
+
+impl Status {
+    pub fn render(&self, format: Format) -> ResultE<String> {
+        Ok(match format {
+            Format::Waybar => serde_json::to_string(&WaybarOutput {
+                text: &self.text,
+                tooltip: self.details.join("\n"),
+                class: self.state.class(),
+                alt: self.state.class(),
+            })?,
+            Format::I3blocks => serde_json::to_string(&I3blocksBlock {
+                name: "ascrs",
+                full_text: &self.text,
+                short_text: &self.short_text,
+                urgent: matches!(self.state, State::NotStarted { .. }),
+            })?,
+            Format::Swiftbar => {
+                let mut output = format!("{}\n---\n", self.text);
+                for line in &self.details {
+                    output += &format!("{}\n", line);
+                }
+                output.trim_end().to_string()
+            }
+        })
+    }
+}
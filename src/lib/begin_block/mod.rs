@@ -1,25 +1,49 @@
-use std::{fs::File, sync::{mpsc::{self, Receiver, TryRecvError}, Mutex}};
+use std::{fs::{self, File}, sync::{mpsc::{self, Receiver, TryRecvError}, Mutex}};
 use cocoa::foundation::{NSAutoreleasePool, NSString};
-use chrono::NaiveDateTime;
+use chrono::{Local, NaiveDateTime};
 use enigo::{KeyboardControllable, Key};
 use fs2::FileExt;
 use objc::{runtime::Object, msg_send};
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use objc_foundation::INSString;
 use {cocoa::base::id, objc::runtime::Sel};
 use {cocoa::base::nil, objc::{declare::ClassDecl, runtime::Class, *}};
 use std::thread;
 use std::time::Duration;
 use std::path::Path;
+use tracing::{error, info, info_span, instrument, warn, Instrument, Span};
 
 mod selfcontrol_api;
 use selfcontrol_api::{start_sc_until, SelfControlError};
 
-use super::{ResultE, Config};
+use super::{
+    config,
+    history::{self, Entry},
+    hooks::{self, Event},
+    metrics::{self, Counter},
+    notifier::{self, Notification, Notifier},
+    webhooks::{self, EventKind},
+    ResultE, Config,
+};
 
 const LOCK_FILE: &str = "/tmp/auto-self-control-rs.lock";
+const DELEGATE_CLASS: &str = "RustNotificationDelegate";
+const SELFCONTROL_CACHE_FILE: &str = "selfcontrol-cache.json";
+// how long SelfControl not running is cached for. A running block can't be stopped early, so it's
+// cached until it ends
+const NOT_RUNNING_CACHE_SECS: i64 = 30;
 
-pub fn begin_block_until(config: &Config, block_end: NaiveDateTime) -> ResultE<()> {
+// the last print-settings result, see cached_selfcontrol_block_end
+#[derive(Serialize, Deserialize)]
+struct SelfControlCache {
+    checked_at: NaiveDateTime,
+    block_end: Option<NaiveDateTime>,
+}
+
+// whether a block was started, false if SelfControl was already running
+#[instrument(skip(config))]
+pub fn begin_block_until(config: &Config, block_end: NaiveDateTime) -> ResultE<bool> {
     // Don't attempt to start selfcontrol if another auto-self-control-rs process is already running
     let lock_file = File::create(&Path::new(LOCK_FILE))?;
     if lock_file.try_lock_exclusive().is_err() {
@@ -27,31 +51,163 @@ pub fn begin_block_until(config: &Config, block_end: NaiveDateTime) -> ResultE<(
     }
 
     if selfcontrol_api::is_active(&config.paths.self_control)?.is_some() {
-        return Ok(());
+        info!("SelfControl is already running");
+        return Ok(false);
     }
-    
+
+    hooks::run(&config.hooks, Event::PreStart, block_end, None);
+    history::record(Entry::StartAttempt { block_end });
+    metrics::count(Counter::ActivationAttempt);
+    let block_start = Local::now().naive_local();
+    let result = start_block_until(config, block_end);
+    match &result {
+        Ok(()) => {
+            info!("block started");
+            let selfcontrol_end = selfcontrol_api::block_end_date(&config.paths.self_control);
+            if let Ok(block_end) = selfcontrol_end {
+                write_selfcontrol_cache(Local::now().naive_local(), block_end);
+            }
+            history::record(Entry::BlockStarted {
+                block_end,
+                selfcontrol_end: selfcontrol_end.ok().flatten(),
+            });
+            notifier::notify(
+                &config.notifications,
+                &Notification::new("Block started", format!("Blocking until {}", block_end.format("%H:%M"))),
+            );
+            hooks::record_started_block(block_end);
+            hooks::run(&config.hooks, Event::OnStart, block_end, None);
+            let event = webhooks::Event::new(EventKind::Started, Some(block_start), block_end, None);
+            webhooks::send(&config.webhooks, event);
+        }
+        Err(e) => {
+            error!("block failed to start: {}", e);
+            // SelfControl errors are notified about as they happen
+            if e.downcast_ref::<SelfControlError>().is_none() {
+                notifier::notify(
+                    &config.notifications,
+                    &Notification::new("Block failed to start", e.to_string()),
+                );
+            }
+            hooks::run(&config.hooks, Event::OnFail, block_end, Some(&e.to_string()));
+            let event = webhooks::Event::new(EventKind::Failed, Some(block_start), block_end, Some(e.to_string()));
+            webhooks::send(&config.webhooks, event);
+        }
+    }
+    result.map(|()| true)
+}
+
+// the end of SelfControl's running block, if there is one
+pub fn selfcontrol_block_end(config: &Config) -> ResultE<Option<NaiveDateTime>> {
+    selfcontrol_api::block_end_date(&config.paths.self_control)
+}
+
+// selfcontrol_block_end, without running print-settings again while the last result still holds,
+// for status bars which ask every few seconds
+pub fn cached_selfcontrol_block_end(config: &Config, now: NaiveDateTime) -> ResultE<Option<NaiveDateTime>> {
+    let path = config::state_dir()?.join(SELFCONTROL_CACHE_FILE);
+    if let Ok(json) = fs::read_to_string(&path) {
+        if let Ok(cache) = serde_json::from_str::<SelfControlCache>(&json) {
+            let fresh = match cache.block_end {
+                Some(block_end) => block_end > now,
+                None => (now - cache.checked_at).num_seconds() < NOT_RUNNING_CACHE_SECS,
+            };
+            if fresh && cache.checked_at <= now {
+                return Ok(cache.block_end);
+            }
+        }
+    }
+    let block_end = selfcontrol_block_end(config)?;
+    write_selfcontrol_cache(now, block_end);
+    Ok(block_end)
+}
+
+fn write_selfcontrol_cache(checked_at: NaiveDateTime, block_end: Option<NaiveDateTime>) {
+    let result = (|| -> ResultE<()> {
+        fs::create_dir_all(config::state_dir()?)?;
+        let cache = SelfControlCache { checked_at, block_end };
+        fs::write(config::state_dir()?.join(SELFCONTROL_CACHE_FILE), serde_json::to_string(&cache)?)?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        warn!("failed to cache SelfControl's state: {}", e);
+    }
+}
+
+fn start_block_until(config: &Config, block_end: NaiveDateTime) -> ResultE<()> {
     let (tx_selfcontrol_event, rx_selfcontrol_event) = mpsc::channel();
     let selfcontrol_path = config.paths.self_control.clone();
+    let span = Span::current();
 
     std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async {
-            loop {
-                let selfcontrol_output = start_sc_until(&selfcontrol_path, block_end).await;
-                tx_selfcontrol_event.send(selfcontrol_output).unwrap();
+            for attempt in 1.. {
+                let attempt_span = info_span!(parent: &span, "start_sc_until", attempt);
+                let selfcontrol_output = start_sc_until(&selfcontrol_path, block_end)
+                    .instrument(attempt_span)
+                    .await;
+                let retry = matches!(
+                    selfcontrol_output,
+                    Err(SelfControlError::UserCancelledHelper) | Err(SelfControlError::NoInputTimeout)
+                );
+                // stop once the outcome is final, as hooks may keep this process running after it
+                if tx_selfcontrol_event.send(selfcontrol_output).is_err() || !retry {
+                    break;
+                }
             }
         });
     });
 
+    let notifier = notifier::from_config_or_disabled(&config.notifications);
     if let Some(pswd) = config.auto_password_input.get_pswd()? {
         set_input_pswd(pswd)?;
         set_should_input_pswd(true)?;
-        wait_for_sc_completion_and_input_password(rx_selfcontrol_event)
+        wait_for_sc_completion_and_input_password(rx_selfcontrol_event, config, notifier.as_ref(), block_end)
     } else {
-        wait_for_sc_completion(rx_selfcontrol_event)
+        wait_for_sc_completion(rx_selfcontrol_event, config, notifier.as_ref(), block_end)
     }
 }
 
+fn report_error(config: &Config, notifier: &dyn Notifier, block_end: NaiveDateTime, e: &SelfControlError) {
+    warn!(error = %e.kind(), "SelfControl: {}", e);
+    history::record(Entry::SelfcontrolError {
+        error: e.kind(),
+        message: e.to_string(),
+    });
+    metrics::count(Counter::Failure(e.kind()));
+    notify_error(config, notifier, block_end, e);
+}
+
+// the user-facing side of report_error, kept apart from the history and metrics it writes
+fn notify_error(config: &Config, notifier: &dyn Notifier, block_end: NaiveDateTime, e: &SelfControlError) {
+    let notification = match e {
+        SelfControlError::UserCancelledHelper => {
+            hooks::run(&config.hooks, Event::OnHelperCancelled, block_end, None);
+            Notification::new(
+                "SelfControl helper cancelled",
+                "The block hasn't started, SelfControl will ask for your password again",
+            )
+        }
+        SelfControlError::NoInputTimeout => Notification::new(
+            "SelfControl didn't start",
+            "The password prompt wasn't answered in time, SelfControl will ask again",
+        ),
+        SelfControlError::NoSuccessMsg => Notification::new(
+            "Block failed to start",
+            "SelfControl didn't report the block as added",
+        ),
+        SelfControlError::CommandError(e) => {
+            Notification::new("Block failed to start", format!("Couldn't run SelfControl: {}", e))
+        }
+        SelfControlError::ParseError(e) => Notification::new(
+            "Block failed to start",
+            format!("Couldn't read SelfControl's output: {}", e),
+        ),
+    };
+    notifier::notify_with(notifier, &notification);
+}
+
 struct PswdInput {
     pswd: String,
     should_input: bool,
@@ -85,13 +241,18 @@ fn set_should_input_pswd(should_input: bool) -> ResultE<()> {
     Ok(())
 }
 
-fn wait_for_sc_completion(rx : Receiver<Result<(), SelfControlError>>) -> ResultE<()> {
+fn wait_for_sc_completion(rx : Receiver<Result<(), SelfControlError>>, config: &Config, notifier: &dyn Notifier, block_end: NaiveDateTime) -> ResultE<()> {
     loop {
         if let Ok(sc_output) = rx.recv() {
             match sc_output {
                 Ok(()) => return Ok(()),
-                Err(SelfControlError::UserCancelledHelper) | Err(SelfControlError::NoInputTimeout) => (),
-                Err(e) => return Err(e.into()),
+                Err(e @ SelfControlError::UserCancelledHelper) | Err(e @ SelfControlError::NoInputTimeout) => {
+                    report_error(config, notifier, block_end, &e);
+                }
+                Err(e) => {
+                    report_error(config, notifier, block_end, &e);
+                    return Err(e.into());
+                }
             }
        } else {
             return Ok(());
@@ -99,17 +260,27 @@ fn wait_for_sc_completion(rx : Receiver<Result<(), SelfControlError>>) -> Result
     }
 }
 
-fn wait_for_sc_completion_and_input_password(rx_selfcontrol_output: Receiver<Result<(), SelfControlError>>) -> ResultE<()> {
+// the delegate receiving app activations, declared on first use. The objc runtime can't declare a
+// class twice, and --daemon starts many blocks in one process
+fn notification_delegate_class() -> ResultE<&'static Class> {
+    if let Some(delegate_class) = Class::get(DELEGATE_CLASS) {
+        return Ok(delegate_class);
+    }
+    let superclass = Class::get("NSObject").ok_or("Failed to get superclass")?;
+    let mut decl = ClassDecl::new(DELEGATE_CLASS, superclass).ok_or("Failed to create delegate class")?;
     unsafe {
-        let _pool = NSAutoreleasePool::new(nil);
-        let superclass = Class::get("NSObject").ok_or("Failed to get superclass")?;
-        let mut decl = ClassDecl::new("RustNotificationDelegate", superclass).ok_or("Failed to create delegate class")?;
-
         decl.add_method(
             Sel::register("applicationDidActivate:"),
             application_did_activate as extern "C" fn(&Object, Sel, id),
         );
-        let delegate_class = decl.register();
+    }
+    Ok(decl.register())
+}
+
+fn wait_for_sc_completion_and_input_password(rx_selfcontrol_output: Receiver<Result<(), SelfControlError>>, config: &Config, notifier: &dyn Notifier, block_end: NaiveDateTime) -> ResultE<()> {
+    unsafe {
+        let _pool = NSAutoreleasePool::new(nil);
+        let delegate_class = notification_delegate_class()?;
         let delegate: *mut Object = msg_send![delegate_class, new];
 
         let workspace: id = msg_send![class!(NSWorkspace), sharedWorkspace];
@@ -122,16 +293,31 @@ fn wait_for_sc_completion_and_input_password(rx_selfcontrol_output: Receiver<Res
             object: nil
         ];
 
+        let result = run_until_sc_completion(rx_selfcontrol_output, config, notifier, block_end);
+
+        // or the delegate would keep typing the password in --daemon, after this block
+        let _: () = msg_send![notification_center, removeObserver: delegate];
+        let _: () = msg_send![delegate, release];
+        result
+    }
+}
+
+fn run_until_sc_completion(rx_selfcontrol_output: Receiver<Result<(), SelfControlError>>, config: &Config, notifier: &dyn Notifier, block_end: NaiveDateTime) -> ResultE<()> {
+    unsafe {
         let run_loop: id = msg_send![class!(NSRunLoop), currentRunLoop];
         
         loop {
             match rx_selfcontrol_output.try_recv() {
                 Ok(sc_output) => match sc_output {
                     Ok(()) => return Ok(()),
-                    Err(SelfControlError::UserCancelledHelper) | Err(SelfControlError::NoInputTimeout) => {
+                    Err(e @ SelfControlError::UserCancelledHelper) | Err(e @ SelfControlError::NoInputTimeout) => {
+                        report_error(config, notifier, block_end, &e);
                         set_should_input_pswd(true)?;
                     },
-                    Err(e) => return Err(e.into()),
+                    Err(e) => {
+                        report_error(config, notifier, block_end, &e);
+                        return Err(e.into());
+                    }
                 }
                 Err(TryRecvError::Disconnected) => return Ok(()),
                 Err(TryRecvError::Empty) => (),
@@ -168,3 +354,40 @@ fn get_focused_app_name() -> String {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use config::ConfigFormat;
+    use notifier::Recording;
+
+    #[test]
+    fn notify_error_notifies_each_selfcontrol_error() {
+        let config = Config::parse("auto-password-input: {}\nblocks: []\n", ConfigFormat::Yaml).unwrap();
+        let block_end = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(14, 0, 0).unwrap();
+
+        let notifier = Recording::default();
+        for e in [
+            SelfControlError::UserCancelledHelper,
+            SelfControlError::NoInputTimeout,
+            SelfControlError::NoSuccessMsg,
+            SelfControlError::CommandError("not found".to_string()),
+        ] {
+            notify_error(&config, &notifier, block_end, &e);
+        }
+
+        let notifications = notifier.0.borrow();
+        let titles: Vec<_> = notifications.iter().map(|n| n.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            [
+                "SelfControl helper cancelled",
+                "SelfControl didn't start",
+                "Block failed to start",
+                "Block failed to start",
+            ]
+        );
+        assert_eq!(notifications[3].body, "Couldn't run SelfControl: not found");
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime};
 use {
     chrono::{self, Local, NaiveTime},
     core_foundation::{
@@ -10,7 +10,8 @@ use {
     std::{collections::HashMap, error::Error, fmt::{self, Display}, path::PathBuf, process::{Command, Output}},
     tokio::process::Command as TokioCommand,
 };
-use super::ResultE;
+use tracing::{debug, trace};
+use super::{super::history::SelfControlErrorKind, ResultE};
 
 
 #[link(name = "CoreFoundation", kind = "framework")]
@@ -31,6 +32,7 @@ pub async fn start_sc_until(
     let duration = super::super::utils::duration_between(now.time(), end.time());
 
     set_block_duration(duration);
+    debug!("starting SelfControl for {} minutes", duration.num_minutes());
 
     let start_self_control = TokioCommand::new(selfcontrol_path)
         .arg("start")
@@ -102,11 +104,7 @@ fn parse_settings(stderr: &str) -> Result<HashMap<String, String>, SelfControlCl
 }
 
 pub fn is_active(self_control_path: &PathBuf) -> ResultE<Option<NaiveTime>> {
-    let output = Command::new(self_control_path)
-        .arg("print-settings")
-        .output()?;
-    let stderr = String::from_utf8(output.stderr)?;
-    let settings_map = parse_settings(&stderr)?;
+    let settings_map = print_settings(self_control_path)?;
 
     let is_active = settings_map
         .get("BlockIsRunning")
@@ -127,6 +125,29 @@ pub fn is_active(self_control_path: &PathBuf) -> ResultE<Option<NaiveTime>> {
     Ok(Some(end_date))
 }
 
+// the end date of the running block, if there is one
+pub fn block_end_date(self_control_path: &PathBuf) -> ResultE<Option<NaiveDateTime>> {
+    let settings_map = print_settings(self_control_path)?;
+    if settings_map.get("BlockIsRunning").map(String::as_str) != Some("1") {
+        return Ok(None);
+    }
+    let end_date = settings_map
+        .get("BlockEndDate")
+        .ok_or(SelfControlCliError::MisingPlistKey)?
+        .replace("\"", "");
+    let end_date = DateTime::parse_from_str(&end_date, "%Y-%m-%d%H:%M:%S%z")?;
+    Ok(Some(end_date.with_timezone(&Local).naive_local()))
+}
+
+fn print_settings(self_control_path: &PathBuf) -> ResultE<HashMap<String, String>> {
+    let output = Command::new(self_control_path)
+        .arg("print-settings")
+        .output()?;
+    let stderr = String::from_utf8(output.stderr)?;
+    trace!("print-settings: {}", stderr);
+    Ok(parse_settings(&stderr)?)
+}
+
 #[derive(Debug)]
 pub enum SelfControlError {
     UserCancelledHelper,
@@ -156,6 +177,18 @@ impl Display for SelfControlError {
 }
 impl Error for SelfControlError {}
 
+impl SelfControlError {
+    pub fn kind(&self) -> SelfControlErrorKind {
+        match self {
+            SelfControlError::UserCancelledHelper => SelfControlErrorKind::UserCancelledHelper,
+            SelfControlError::NoInputTimeout => SelfControlErrorKind::NoInputTimeout,
+            SelfControlError::NoSuccessMsg => SelfControlErrorKind::NoSuccessMsg,
+            SelfControlError::CommandError(_) => SelfControlErrorKind::CommandError,
+            SelfControlError::ParseError(_) => SelfControlErrorKind::ParseError,
+        }
+    }
+}
+
 #[derive(Debug)]
 enum SelfControlCliError {
     InvalidPlistFormat,
@@ -0,0 +1,178 @@
+// Missed block starts. If the Mac is asleep or off when a block starts, the agent's RunAtLoad or
+// next 30s poll starts it late. When --execute first runs during a block more than
+// catch-up.grace-minutes after it started, the missed portion is recorded in the history as a
+// missed-block record, and catch-up.policy decides what happens to the rest of the block:
+//   remainder (default): start it for the remainder of the block
+//   extend: start it, and extend it by the missed time
+//   skip: don't start it
+// The decision for the current block is kept in catch-up.json in the state dir, so the later runs
+// during the block stick to it.
+use chrono::{Duration, NaiveDateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs,
+};
+use tracing::warn;
+
+use super::{
+    config::{self, Config},
+    history::{self, Entry},
+    notifier::{self, Notification},
+    ResultE,
+};
+
+const CATCH_UP_FILE: &str = "catch-up.json";
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, PartialEq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CatchUp {
+    policy: Policy,
+    grace_minutes: u32,
+}
+
+impl CatchUp {
+    // whether new blocks less of a missed block than self: a policy blocking less of it, or with the
+    // same policy a grace period which extends less (extend) or skips more blocks (skip)
+    pub fn is_weakened_by(&self, new: &CatchUp) -> bool {
+        let rank = |policy: Policy| match policy {
+            Policy::Extend => 0,
+            Policy::Remainder => 1,
+            Policy::Skip => 2,
+        };
+        match self.policy {
+            _ if new.policy != self.policy => rank(new.policy) > rank(self.policy),
+            Policy::Extend => new.grace_minutes > self.grace_minutes,
+            Policy::Skip => new.grace_minutes < self.grace_minutes,
+            Policy::Remainder => false,
+        }
+    }
+}
+
+impl Display for CatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}m grace", self.policy.name(), self.grace_minutes)
+    }
+}
+
+impl Default for CatchUp {
+    fn default() -> Self {
+        Self {
+            policy: Policy::Remainder,
+            grace_minutes: 2,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    Remainder,
+    Extend,
+    Skip,
+}
+
+impl Policy {
+    fn name(&self) -> &'static str {
+        match self {
+            Policy::Remainder => "remainder",
+            Policy::Extend => "extend",
+            Policy::Skip => "skip",
+        }
+    }
+}
+
+// what to do about the block active now
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "decision", rename_all = "kebab-case")]
+pub enum Decision {
+    StartUntil { block_end: NaiveDateTime },
+    Skip,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockDecision {
+    block_start: NaiveDateTime,
+    decision: Decision,
+}
+
+pub fn decide(
+    config: &Config,
+    block_start: NaiveDateTime,
+    block_end: NaiveDateTime,
+    now: NaiveDateTime,
+) -> ResultE<Decision> {
+    let path = config::state_dir()?.join(CATCH_UP_FILE);
+    if path.exists() {
+        let previous: BlockDecision = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        if previous.block_start == block_start {
+            return Ok(previous.decision);
+        }
+    }
+
+    let catch_up = &config.catch_up;
+    let missed = now - block_start;
+    let decision = match missed > Duration::minutes(catch_up.grace_minutes as i64) {
+        false => Decision::StartUntil { block_end },
+        true => {
+            history::record(Entry::MissedBlock {
+                block_start,
+                block_end,
+                missed_minutes: missed.num_minutes(),
+                policy: catch_up.policy,
+            });
+            let message = format!(
+                "The block from {} to {} should have started {} minutes ago (catch-up.policy: {})",
+                block_start.format("%H:%M"),
+                block_end.format("%H:%M"),
+                missed.num_minutes(),
+                catch_up.policy.name()
+            );
+            warn!("{}", message);
+            notifier::notify(&config.notifications, &Notification::new("Block start missed", message));
+            match catch_up.policy {
+                Policy::Remainder => Decision::StartUntil { block_end },
+                Policy::Extend => Decision::StartUntil {
+                    block_end: block_end + Duration::seconds(missed.num_seconds()),
+                },
+                Policy::Skip => Decision::Skip,
+            }
+        }
+    };
+
+    fs::create_dir_all(config::state_dir()?)?;
+    fs::write(
+        path,
+        serde_json::to_string(&BlockDecision {
+            block_start,
+            decision,
+        })?,
+    )?;
+    Ok(decision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catch_up(policy: Policy, grace_minutes: u32) -> CatchUp {
+        CatchUp { policy, grace_minutes }
+    }
+
+    #[test]
+    fn policy_blocking_less_is_weaker() {
+        let remainder = catch_up(Policy::Remainder, 2);
+        assert!(remainder.is_weakened_by(&catch_up(Policy::Skip, 0)));
+        assert!(catch_up(Policy::Extend, 2).is_weakened_by(&remainder));
+        assert!(!remainder.is_weakened_by(&catch_up(Policy::Extend, 2)));
+    }
+
+    #[test]
+    fn grace_minutes_weaken_by_policy() {
+        assert!(catch_up(Policy::Skip, 5).is_weakened_by(&catch_up(Policy::Skip, 0)));
+        assert!(!catch_up(Policy::Skip, 0).is_weakened_by(&catch_up(Policy::Skip, 5)));
+        assert!(catch_up(Policy::Extend, 2).is_weakened_by(&catch_up(Policy::Extend, 10)));
+        assert!(!catch_up(Policy::Remainder, 2).is_weakened_by(&catch_up(Policy::Remainder, 10)));
+    }
+}
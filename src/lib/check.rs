@@ -0,0 +1,161 @@
+use chrono::NaiveTime;
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    config::{Config, Day, WEEKDAYS},
+    metrics, notifier, signing,
+    utils::minute_ranges,
+    webhooks,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    file: PathBuf,
+    // 1-indexed (line, column)
+    location: Option<(usize, usize)>,
+    message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some((line, column)) = self.location {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, ": {}: {}", severity, self.message)
+    }
+}
+
+pub fn check_config(config_path: &Path) -> Vec<Diagnostic> {
+    let diagnostic = |severity, location, message| Diagnostic {
+        severity,
+        file: config_path.to_path_buf(),
+        location,
+        message,
+    };
+
+    let config = match Config::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                file: e.file,
+                location: e.error.location,
+                message: e.error.message,
+            }]
+        }
+    };
+
+    lint(&config)
+        .into_iter()
+        .map(|message| diagnostic(Severity::Warning, None, message))
+        .collect()
+}
+
+fn lint(config: &Config) -> Vec<String> {
+    let mut warnings = vec![];
+
+    let mut days = config.blocks.keys().copied().collect::<Vec<_>>();
+    days.sort_by_key(|day| match day {
+        Day::All => 0,
+        Day::WeekDay(weekday) => weekday.number_from_monday(),
+    });
+
+    for day in &days {
+        let times = &config.blocks[day];
+        for (start, end) in times {
+            if start == end {
+                warnings.push(format!(
+                    "{}: block {} has zero length and will be treated as a 24-hour block",
+                    day_name(day),
+                    format_pair(*start, *end)
+                ));
+            }
+        }
+
+        let times = times
+            .iter()
+            .filter(|(start, end)| start != end)
+            .collect::<Vec<_>>();
+        for (i, a) in times.iter().enumerate() {
+            for b in &times[i + 1..] {
+                if overlaps(**a, **b) {
+                    warnings.push(format!(
+                        "{}: blocks {} and {} overlap",
+                        day_name(day),
+                        format_pair(a.0, a.1),
+                        format_pair(b.0, b.1)
+                    ));
+                }
+            }
+        }
+    }
+
+    if config.blocks.contains_key(&Day::All)
+        && WEEKDAYS
+            .iter()
+            .all(|weekday| config.blocks.contains_key(&Day::WeekDay(*weekday)))
+    {
+        warnings.push(
+            "All: every weekday is explicitly defined, so blocks for 'All' will never be used"
+                .to_string(),
+        );
+    }
+
+    if !config.paths.self_control.exists() {
+        warnings.push(format!(
+            "paths.self-control: {} does not exist",
+            config.paths.self_control.display()
+        ));
+    }
+
+    if let Err(e) = notifier::from_config(&config.notifications) {
+        warnings.push(e.to_string());
+    }
+
+    if let Err(e) = webhooks::check_url(&config.webhooks) {
+        warnings.push(e.to_string());
+    }
+
+    if let Err(e) = metrics::listen_address(&config.metrics) {
+        warnings.push(e.to_string());
+    }
+
+    if let Some(public_key) = &config.partner_public_key {
+        if let Err(e) = signing::parse_public_key(public_key) {
+            warnings.push(e.to_string());
+        }
+    }
+
+    warnings
+}
+
+fn day_name(day: &Day) -> String {
+    match day {
+        Day::All => "All".to_string(),
+        Day::WeekDay(weekday) => weekday.to_string(),
+    }
+}
+
+fn format_pair(start: NaiveTime, end: NaiveTime) -> String {
+    format!("[{}, {}]", start.format("%H:%M"), end.format("%H:%M"))
+}
+
+fn overlaps(a: (NaiveTime, NaiveTime), b: (NaiveTime, NaiveTime)) -> bool {
+    let (a, b) = (minute_ranges(a), minute_ranges(b));
+    a.iter().any(|a| b.iter().any(|b| a.0 < b.1 && b.0 < a.1))
+}
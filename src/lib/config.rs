@@ -1,26 +1,231 @@
 use std::{
+    error::Error,
+    fmt::{self, Display},
     io::ErrorKind,
     path::{Path, PathBuf},
     process::Command,
     env
 };
-use super::ResultE;
-use chrono::{self, Datelike, Local, NaiveTime};
+use super::{
+    catch_up::CatchUp, hooks::Hooks, layers::{self, MergedConfig}, metrics::Metrics,
+    migrations::{self, MigrationError}, notifier::Notifications, webhooks::Webhooks, ResultE,
+};
+use chrono::{self, Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
 use serde::Deserialize;
 use std::{collections::HashMap, fs};
+use tracing::{debug, info, instrument};
 use security_framework::passwords::{get_generic_password, set_generic_password};
 use serde::de::{self, Visitor};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, StringValidation},
+    JsonSchema,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub const ALL: [ConfigFormat; 3] = [ConfigFormat::Yaml, ConfigFormat::Toml, ConfigFormat::Json];
+
+    pub fn from_path(path: &Path) -> ResultE<Self> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        match extension {
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!(
+                "unsupported config file extension: {}, expected .yaml, .toml or .json",
+                path.display()
+            )
+            .into()),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConfigFormat::Yaml => "yaml",
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Json => "json",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    // 1-indexed (line, column)
+    pub location: Option<(usize, usize)>,
+}
 
-#[derive(Debug, Deserialize)]
+impl ParseError {
+    pub(super) fn from_yaml(e: serde_yaml::Error) -> Self {
+        let location = e.location().map(|l| (l.line(), l.column()));
+        // serde_yaml appends "at line x column y" to its messages, we store it separately
+        let message = e.to_string();
+        let message = match location {
+            Some(_) => message.split(" at line ").next().unwrap_or(&message).to_string(),
+            None => message,
+        };
+        Self { message, location }
+    }
+
+    pub(super) fn from_toml(e: toml::de::Error, config_file: &str) -> Self {
+        let location = e.span().map(|span| {
+            let before = &config_file[..span.start];
+            let line = before.matches('\n').count() + 1;
+            let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+            (line, column)
+        });
+        Self { message: e.message().to_string(), location }
+    }
+
+    pub(super) fn from_json(e: serde_json::Error) -> Self {
+        let location = match e.line() {
+            0 => None,
+            line => Some((line, e.column())),
+        };
+        let message = e.to_string();
+        let message = match location {
+            Some(_) => message.split(" at line ").next().unwrap_or(&message).to_string(),
+            None => message,
+        };
+        Self { message, location }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some((line, column)) = self.location {
+            write!(f, " at line {} column {}", line, column)?;
+        }
+        Ok(())
+    }
+}
+impl Error for ParseError {}
+
+impl From<&str> for ParseError {
+    fn from(message: &str) -> Self {
+        Self { message: message.to_string(), location: None }
+    }
+}
+
+impl From<String> for ParseError {
+    fn from(message: String) -> Self {
+        Self { message, location: None }
+    }
+}
+
+impl From<std::io::Error> for ParseError {
+    fn from(e: std::io::Error) -> Self {
+        e.to_string().into()
+    }
+}
+
+impl From<Box<dyn Error>> for ParseError {
+    fn from(e: Box<dyn Error>) -> Self {
+        e.to_string().into()
+    }
+}
+
+// a ParseError in a specific config file, of possibly several layered ones
+#[derive(Debug)]
+pub struct ConfigError {
+    pub file: PathBuf,
+    pub error: ParseError,
+    // the file's version is newer than this build supports
+    pub newer_version: bool,
+}
+
+impl ConfigError {
+    pub fn new(file: &Path, error: ParseError) -> Self {
+        Self { file: file.to_path_buf(), error, newer_version: false }
+    }
+
+    pub fn from_migration(file: &Path, error: MigrationError) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            error: error.to_string().into(),
+            newer_version: matches!(error, MigrationError::NewerVersion(_)),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some((line, column)) = self.error.location {
+            write!(f, ":{}:{}", line, column)?;
+        }
+        write!(f, ": {}", self.error.message)
+    }
+}
+impl Error for ConfigError {}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Config {
+    // see migrations.rs, configs are always migrated to the current version before being parsed
+    #[allow(dead_code)]
+    #[serde(default)]
+    version: u64,
     #[serde(default)]
     pub paths: Paths,
     pub auto_password_input: AutoPasswordInput,
+    #[serde(default)]
+    pub block_warning: BlockWarning,
+    #[serde(default)]
+    pub notifications: Notifications,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default)]
+    pub webhooks: Webhooks,
+    #[serde(default)]
+    pub catch_up: CatchUp,
+    #[serde(default)]
+    pub metrics: Metrics,
+    // whether --deploy installs --daemon rather than --execute every 30 seconds, see daemon.rs
+    #[serde(default)]
+    pub(super) daemon: bool,
+    // see check_not_weakened_by
+    #[serde(default)]
+    pub(super) strict: bool,
+    // how long changes that reduce blocked time are queued for before applying, see cooldown.rs
+    #[serde(default)]
+    pub(super) cooldown_hours: u32,
+    // how far in advance a pause must be requested, see pause.rs
+    #[serde(default = "default_pause_notice_hours")]
+    pub(super) pause_notice_hours: u32,
+    // how many blocks --skip_next may cancel each week, see skips.rs
+    #[serde(default)]
+    pub(super) skips_per_week: u32,
+    // base64 ed25519 key which must sign changes that weaken the config, see signing.rs
+    #[serde(default)]
+    pub(super) partner_public_key: Option<String>,
     #[serde(deserialize_with = "deserialize_blocks")]
-    blocks: HashMap<Day, Vec<(NaiveTime, NaiveTime)>>,
+    #[schemars(with = "Vec<DaysTimesPair>")]
+    pub(super) blocks: HashMap<Day, Vec<(NaiveTime, NaiveTime)>>,
+}
+
+fn default_pause_notice_hours() -> u32 {
+    24
 }
 
+pub const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
 #[derive(Deserialize, Debug, PartialEq, Eq, Hash, Copy, Clone)]
 pub enum Day {
     All,
@@ -28,7 +233,27 @@ pub enum Day {
     WeekDay(chrono::Weekday),
 }
 
-#[derive(Debug, Deserialize)]
+// serde's untagged variants aren't understood by schemars, so list the accepted names by hand
+impl JsonSchema for Day {
+    fn schema_name() -> String {
+        "Day".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        let names = [
+            "All", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun", "Monday", "Tuesday",
+            "Wednesday", "Thursday", "Friday", "Saturday", "Sunday",
+        ];
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(names.iter().map(|name| (*name).into()).collect()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
 pub struct Paths {
     pub self_control: PathBuf,
@@ -48,7 +273,7 @@ impl Default for Paths {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
 pub struct AutoPasswordInput {
     enable: bool,
@@ -64,7 +289,15 @@ impl Default for AutoPasswordInput {
     }
 }
 
-fn get_account_name() -> String {
+// see warning.rs
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BlockWarning {
+    pub minutes: u32,
+    pub command: Option<String>,
+}
+
+pub(super) fn get_account_name() -> String {
     env::var("USERNAME")
         .or_else(|_| env::var("USER"))
         .unwrap_or_else(|_| {
@@ -98,56 +331,78 @@ impl AutoPasswordInput {
     }
 }
 
-fn deserialize_blocks<'de, D>(
-    deserializer: D,
-) -> Result<HashMap<Day, Vec<(NaiveTime, NaiveTime)>>, D::Error>
+#[derive(Deserialize, JsonSchema)]
+struct DaysTimesPair {
+    days: Vec<Day>,
+    #[serde(deserialize_with = "deserialize_times")]
+    #[schemars(schema_with = "times_schema")]
+    times: Vec<(NaiveTime, NaiveTime)>,
+}
+
+fn times_schema(gen: &mut SchemaGenerator) -> Schema {
+    let time = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some("^([01]?[0-9]|2[0-3]):[0-5][0-9]$".to_string()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    let mut pair = gen.subschema_for::<[String; 2]>().into_object();
+    if let Some(array) = pair.array.as_mut() {
+        array.items = Some(Schema::from(time).into());
+    }
+    let mut times = gen.subschema_for::<Vec<String>>().into_object();
+    if let Some(array) = times.array.as_mut() {
+        array.items = Some(Schema::from(pair).into());
+    }
+    times.into()
+}
+
+fn deserialize_times<'de, D>(deserializer: D) -> Result<Vec<(NaiveTime, NaiveTime)>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    #[derive(Deserialize)]
-    struct DaysTimesPair {
-        days: Vec<Day>,
-        #[serde(deserialize_with = "deserialize_times")]
-        times: Vec<(NaiveTime, NaiveTime)>,
-    }
-
-    fn deserialize_times<'de, D>(deserializer: D) -> Result<Vec<(NaiveTime, NaiveTime)>, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        struct NaivesVisitor;
-
-        impl<'de> Visitor<'de> for NaivesVisitor {
-            type Value = Vec<(NaiveTime, NaiveTime)>;
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("a sequence of (xx::xx, xx::xx)")
-            }
+    struct NaivesVisitor;
 
-            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
-            where
-                A: serde::de::SeqAccess<'de>,
-            {
-                let mut times_vec = vec![];
-                while let Some(pair) = seq.next_element::<Vec<&str>>()? {
-                    if pair.len() != 2 {
-                        return Err(de::Error::custom("More than two times specified"));
-                    }
-                    let (start, end) = (
-                        NaiveTime::parse_from_str(pair[0], "%H:%M"),
-                        NaiveTime::parse_from_str(pair[1], "%H:%M"),
-                    );
+    impl<'de> Visitor<'de> for NaivesVisitor {
+        type Value = Vec<(NaiveTime, NaiveTime)>;
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a sequence of (xx::xx, xx::xx)")
+        }
 
-                    if start.is_err() || end.is_err() {
-                        return Err(de::Error::custom("invalid time format"));
-                    }
-                    times_vec.push((start.unwrap(), end.unwrap()));
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::SeqAccess<'de>,
+        {
+            let mut times_vec = vec![];
+            // owned strings, as not every format can lend us borrowed ones (eg toml)
+            while let Some(pair) = seq.next_element::<Vec<String>>()? {
+                if pair.len() != 2 {
+                    return Err(de::Error::custom("More than two times specified"));
                 }
-                return Ok(times_vec);
+                let (start, end) = (
+                    NaiveTime::parse_from_str(&pair[0], "%H:%M"),
+                    NaiveTime::parse_from_str(&pair[1], "%H:%M"),
+                );
+
+                if start.is_err() || end.is_err() {
+                    return Err(de::Error::custom("invalid time format"));
+                }
+                times_vec.push((start.unwrap(), end.unwrap()));
             }
+            return Ok(times_vec);
         }
-        deserializer.deserialize_seq(NaivesVisitor)
     }
+    deserializer.deserialize_seq(NaivesVisitor)
+}
 
+fn deserialize_blocks<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<Day, Vec<(NaiveTime, NaiveTime)>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
     struct BlocksVisitor;
     impl<'de> Visitor<'de> for BlocksVisitor {
         type Value = HashMap<Day, Vec<(NaiveTime, NaiveTime)>>;
@@ -177,24 +432,165 @@ where
 
 impl Config {
     pub fn build(config_path: &Path) -> ResultE<Self> {
-        let config_file = fs::read_to_string(config_path)?;
-        serde_yaml::from_str(&config_file).map_err(|e| e.into())
+        Self::load(config_path).map_err(|e| e.into())
     }
 
-    pub fn get_currently_active_block(&self) -> Option<(NaiveTime, NaiveTime)> {
-        let now = Local::now();
-        let time_now = Local::now().time();
-        let weekday = now.date_naive().weekday();
+    // parses config_path along with any files layered on top of it, see layers.rs
+    pub fn load(config_path: &Path) -> Result<Self, ConfigError> {
+        let merged = layers::load(config_path)?;
+        Self::from_merged(config_path, merged)?
+            .with_env_overrides()
+            .map_err(|e| ConfigError::new(config_path, e.into()))
+    }
 
-        let blocks: &Vec<(NaiveTime, NaiveTime)>;
-        if let Some(v) = self.blocks.get(&Day::WeekDay(weekday)) {
-            blocks = v;
-        } else if let Some(v) = self.blocks.get(&Day::All) {
-            blocks = v;
-        } else {
-            return None;
+    // a merged config value saved by a previous run, see reload.rs
+    pub(super) fn from_snapshot(snapshot: serde_json::Value) -> ResultE<Self> {
+        let serde_json::Value::Object(mut snapshot) = snapshot else {
+            return Err("invalid config snapshot".into());
+        };
+        migrations::migrate(&mut snapshot)?;
+        let config: Self = serde_json::from_value(serde_json::Value::Object(snapshot))?;
+        config.with_env_overrides()
+    }
+
+    fn with_env_overrides(mut self) -> ResultE<Self> {
+        for env_override in env_overrides()? {
+            debug!(
+                "{} = {} (from ${})",
+                env_override.key, env_override.value, env_override.var
+            );
+            self.apply_env_override(env_override);
+        }
+        Ok(self)
+    }
+
+    fn apply_env_override(&mut self, env_override: EnvOverride) {
+        use serde_json::Value;
+        match (env_override.key, env_override.value) {
+            ("paths.self-control", Value::String(path)) => self.paths.self_control = path.into(),
+            ("paths.launch-agents", Value::String(path)) => self.paths.launch_agents = path.into(),
+            ("auto-password-input.enable", Value::Bool(enable)) => {
+                self.auto_password_input.enable = enable
+            }
+            (key, _) => unreachable!("unhandled env override {}", key),
+        }
+    }
+
+    fn from_merged(config_path: &Path, merged: MergedConfig) -> Result<Self, ConfigError> {
+        match merged.single_file {
+            Some((config_file, format)) => Self::parse(&config_file, format),
+            None => serde_json::from_value(merged.value).map_err(|e| e.to_string().into()),
+        }
+        .map_err(|e| ConfigError::new(config_path, e))
+    }
+
+    pub fn parse(config_file: &str, format: ConfigFormat) -> Result<Self, ParseError> {
+        match format {
+            ConfigFormat::Yaml => serde_yaml::from_str(config_file).map_err(ParseError::from_yaml),
+            ConfigFormat::Toml => {
+                toml::from_str(config_file).map_err(|e| ParseError::from_toml(e, config_file))
+            }
+            ConfigFormat::Json => serde_json::from_str(config_file).map_err(ParseError::from_json),
+        }
+    }
+
+    pub fn get_active_block(&self, now: NaiveDateTime) -> Option<(NaiveTime, NaiveTime)> {
+        let blocks = self.get_blocks(now.date().weekday())?;
+        Self::find_block(now.time(), blocks)
+    }
+
+    // the blocks in force on weekday- explicitly defined weekdays override All
+    pub fn get_blocks(&self, weekday: Weekday) -> Option<&Vec<(NaiveTime, NaiveTime)>> {
+        self.blocks
+            .get(&Day::WeekDay(weekday))
+            .or_else(|| self.blocks.get(&Day::All))
+    }
+
+    // when the block active at now ends, if there is one
+    pub fn get_active_block_end(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let (block_start_time, block_end_time) = self.get_active_block(now)?;
+
+        let mut block_end = now.date().and_time(block_end_time);
+        // the block runs overnight and we're before midnight
+        if block_start_time >= block_end_time && now.time() >= block_start_time {
+            block_end += Duration::days(1);
+        }
+        Some(block_end)
+    }
+
+    // when the block active at now started, if there is one
+    pub fn get_active_block_start(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let (block_start_time, block_end_time) = self.get_active_block(now)?;
+
+        let mut block_start = now.date().and_time(block_start_time);
+        // the block runs overnight and we're after midnight
+        if block_start_time >= block_end_time && now.time() < block_start_time {
+            block_start -= Duration::days(1);
+        }
+        Some(block_start)
+    }
+
+    // the (start, end) of each block starting after now and within the next week, in order
+    pub fn get_upcoming_blocks(&self, now: NaiveDateTime) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let mut blocks = (0..=7)
+            .flat_map(|days| {
+                let date = now.date() + Duration::days(days);
+                self.get_blocks(date.weekday())
+                    .into_iter()
+                    .flatten()
+                    .map(move |(start, _)| date.and_time(*start))
+            })
+            .filter(|start| *start > now)
+            .filter_map(|start| Some((start, self.get_active_block_end(start)?)))
+            .collect::<Vec<_>>();
+        blocks.sort();
+        blocks.dedup();
+        blocks
+    }
+
+    // the (start, end) of each block overlapping from..to, in order
+    pub fn get_blocks_between(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        // a block overlapping from may have started the day before
+        let days = (to.date() - from.date()).num_days();
+        let mut blocks = (-1..=days)
+            .flat_map(|days| {
+                let date = from.date() + Duration::days(days);
+                self.get_blocks(date.weekday())
+                    .into_iter()
+                    .flatten()
+                    .map(move |(start, _)| date.and_time(*start))
+            })
+            .filter_map(|start| Some((start, self.get_active_block_end(start)?)))
+            .filter(|(start, end)| *start < to && *end > from)
+            .collect::<Vec<_>>();
+        blocks.sort();
+        blocks.dedup();
+        blocks
+    }
+
+    // strict mode: while a block is active, a new config may not remove, shorten or delay it
+    pub fn check_not_weakened_by(&self, new: &Config, now: NaiveDateTime) -> ResultE<()> {
+        if !self.strict {
+            return Ok(());
+        }
+        let Some(block_end) = self.get_active_block_end(now) else { return Ok(()) };
+
+        // turning strict mode off, then weakening the block, would otherwise get around it. A weaker
+        // catch-up could skip the block before --execute first starts it
+        let catch_up_weakened = self.catch_up.is_weakened_by(&new.catch_up);
+        match new.get_active_block_end(now) {
+            Some(new_block_end) if new_block_end >= block_end && new.strict && !catch_up_weakened => Ok(()),
+            _ => Err(format!(
+                "strict mode: the new config removes, shortens or delays the active block (or turns \
+                strict mode off, or weakens catch-up), it can only take effect after the block ends at {}",
+                block_end.format("%H:%M")
+            )
+            .into()),
         }
-        Self::find_block(time_now, blocks)
     }
 
     fn find_block(
@@ -233,21 +629,250 @@ impl Config {
         }
     }
 
+    pub fn is_agent_installed(&self, name: &str) -> bool {
+        self.paths.launch_agents.join(name).exists()
+    }
+
+    #[instrument(skip(self, plist))]
     pub fn install_agent(&self, name: &str, plist: &str) -> ResultE<()> {
         self.remove_agent(name)?;
         let path = Path::new(&self.paths.launch_agents).join(name);
         fs::write(&path, plist)?;
 
-        Command::new("launchctl").arg("load").arg(&path).output()?;
+        let output = Command::new("launchctl").arg("load").arg(&path).output()?;
+        info!("installed {}: launchctl load {}", path.display(), output.status);
         Ok(())
     }
 }
 
-pub fn build_example_config() -> String {
+// Config values that can be overridden by environment variables, without editing the config file.
+// The variable for a key is ASCRS_ followed by the key uppercased, with . and - replaced by _,
+// eg paths.self-control is overridden by ASCRS_PATHS_SELF_CONTROL.
+const ENV_OVERRIDABLE_KEYS: [&str; 3] = [
+    "paths.self-control",
+    "paths.launch-agents",
+    "auto-password-input.enable",
+];
+
+#[derive(Debug)]
+pub struct EnvOverride {
+    pub key: &'static str,
+    pub var: String,
+    pub value: serde_json::Value,
+}
+
+pub fn env_overrides() -> ResultE<Vec<EnvOverride>> {
+    let mut overrides = vec![];
+    for key in ENV_OVERRIDABLE_KEYS {
+        let var = format!("ASCRS_{}", key.to_uppercase().replace(['.', '-'], "_"));
+        let Ok(value) = env::var(&var) else { continue };
+
+        let value = match key {
+            "auto-password-input.enable" => match value.to_lowercase().as_str() {
+                "true" | "1" => true.into(),
+                "false" | "0" => false.into(),
+                _ => return Err(format!("{}: expected true or false, found {}", var, value).into()),
+            },
+            _ => value.into(),
+        };
+        overrides.push(EnvOverride { key, var, value });
+    }
+    Ok(overrides)
+}
+
+// the config after layering and environment variable overrides, in the format of config_path.
+// The overridden values are logged at debug level, see -v
+pub fn build_merged_config(config_path: &Path) -> ResultE<String> {
+    let merged = layers::load(config_path)?;
+    let mut value = merged.value.clone();
+    Config::from_merged(config_path, merged)?.with_env_overrides()?;
+
+    for env_override in env_overrides()? {
+        let (table, key) = env_override.key.split_once('.').ok_or("invalid override key")?;
+        let table = value
+            .as_object_mut()
+            .ok_or("invalid merged config")?
+            .entry(table)
+            .or_insert(serde_json::Value::Null);
+        // a table with only commented out keys is null in yaml
+        if table.is_null() {
+            *table = serde_json::Value::Object(Default::default());
+        }
+        if let Some(table) = table.as_object_mut() {
+            table.insert(key.to_string(), env_override.value.clone());
+        }
+    }
+
+    serialize_value(&value, ConfigFormat::from_path(config_path)?)
+}
+
+// rewrites an outdated config_path in the current version, keeping the original as a .bak file.
+// Returns the version the file was migrated from, or None if it was already up to date
+pub fn migrate_config_file(config_path: &Path) -> ResultE<Option<u64>> {
+    let format = ConfigFormat::from_path(config_path)?;
+    let config_file = fs::read_to_string(config_path)?;
+    let mut value = layers::parse_value(&config_file, format)
+        .map_err(|e| ConfigError::new(config_path, e))?;
+
+    let version = migrations::migrate(&mut value)?;
+    if version == migrations::CURRENT_VERSION {
+        return Ok(None);
+    }
+
+    let backup_path = config_path.with_extension(format!("{}.bak", format.extension()));
+    fs::copy(config_path, backup_path)?;
+    fs::write(config_path, serialize_value(&serde_json::Value::Object(value), format)?)?;
+    Ok(Some(version))
+}
+
+fn serialize_value(value: &serde_json::Value, format: ConfigFormat) -> ResultE<String> {
+    match format {
+        ConfigFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.into()),
+        ConfigFormat::Toml => toml::to_string(value).map_err(|e| e.into()),
+        ConfigFormat::Json => serde_json::to_string_pretty(value).map_err(|e| e.into()),
+    }
+}
+
+pub fn build_json_schema() -> ResultE<String> {
+    // include and hosts are consumed while layering, so aren't fields of Config
+    let mut gen = SchemaGenerator::default();
+    let include = gen.subschema_for::<Vec<PathBuf>>();
+    let hosts = gen.subschema_for::<HashMap<String, serde_json::Map<String, serde_json::Value>>>();
+
+    let mut schema = gen.into_root_schema_for::<Config>();
+    let properties = &mut schema.schema.object().properties;
+    properties.insert("include".to_string(), include);
+    properties.insert("hosts".to_string(), hosts);
+    serde_json::to_string_pretty(&schema).map_err(|e| e.into())
+}
+
+pub const CONFIG_PATH_ENV: &str = "ASCRS_CONFIG";
+
+// a config file chosen by the user, --config takes precedence over $ASCRS_CONFIG
+pub fn explicit_config_path(cli_config_path: Option<&PathBuf>) -> Option<PathBuf> {
+    cli_config_path.cloned().or_else(|| {
+        env::var_os(CONFIG_PATH_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    })
+}
+
+// $XDG_CONFIG_HOME/auto-selfcontrol-rs/ if set, else ~/.config/auto-selfcontrol-rs/
+pub fn config_dir() -> ResultE<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+// where runtime state is kept, $XDG_STATE_HOME/auto-selfcontrol-rs/ if set, else
+// ~/.local/state/auto-selfcontrol-rs/
+pub fn state_dir() -> ResultE<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+// where records are kept, $XDG_DATA_HOME/auto-selfcontrol-rs/ if set, else
+// ~/.local/share/auto-selfcontrol-rs/
+pub fn data_dir() -> ResultE<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+// XDG_STATE_HOME and XDG_DATA_HOME, where state_dir and data_dir honour them, for launch agents,
+// which launchd starts without the environment they were deployed from
+pub fn xdg_environment() -> Vec<(&'static str, String)> {
+    ["XDG_STATE_HOME", "XDG_DATA_HOME"]
+        .into_iter()
+        .filter_map(|var| Some((var, env::var(var).ok()?)))
+        .filter(|(_, path)| Path::new(path).is_absolute())
+        .collect()
+}
+
+fn xdg_dir(var: &str, default_in_home: &str) -> ResultE<PathBuf> {
+    // the xdg spec says relative paths are invalid and should be ignored
+    let xdg_dir = env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute());
+
+    let base_dir = match xdg_dir {
+        Some(base_dir) => base_dir,
+        None => {
+            let home_dir = env::var_os("HOME").ok_or_else(|| "HOME environment variable not set")?;
+            Path::new(&home_dir).join(default_in_home)
+        }
+    };
+    Ok(base_dir.join("auto-selfcontrol-rs/"))
+}
+
+// the config file in config_dir, in whichever supported format it was written
+pub fn find_config_file(config_dir: &Path) -> ResultE<PathBuf> {
+    let existing = ConfigFormat::ALL
+        .iter()
+        .map(|format| config_dir.join(format!("config.{}", format.extension())))
+        .filter(|path| path.exists())
+        .collect::<Vec<_>>();
+
+    match existing.len() {
+        0 => Ok(config_dir.join("config.yaml")),
+        1 => Ok(existing[0].clone()),
+        _ => Err(format!(
+            "multiple config files found in {}, remove all but one",
+            config_dir.display()
+        )
+        .into()),
+    }
+}
+
+pub fn build_example_config(format: ConfigFormat) -> String {
+    match format {
+        ConfigFormat::Yaml => build_example_yaml_config(),
+        ConfigFormat::Toml => build_example_toml_config(),
+        ConfigFormat::Json => build_example_json_config(),
+    }
+}
+
+fn build_example_yaml_config() -> String {
     format!(
-"auto-password-input:
+"version: {}
+
+#strict: true # optional, defaults to false. While a block is active, refuse config changes that
+              # remove, shorten or delay it until the block has ended
+#cooldown-hours: 24 # optional, defaults to 0. Changes that reduce blocked time only apply this
+                    # many hours after they were made
+#pause-notice-hours: 48 # optional, defaults to 24. How far in advance --pause must be requested
+#skips-per-week: 2 # optional, defaults to 0. How many upcoming blocks --skip_next may cancel a week
+#partner-public-key: ... # optional. Changes that reduce blocked time must be signed with the
+                         # matching private key, see --sign_config
+#daemon: true # optional, defaults to false. --deploy installs a long-running --daemon instead of
+              # running --execute every 30 seconds
+
+auto-password-input:
   #enable: ...  # optional, defaults to false 
-  #account_name: ... # optional, defaults to $USER else $whoami 
+  #account-name: ... # optional, defaults to $USER else $whoami 
+
+#block-warning:
+  #minutes: 5 # optional, defaults to 0 (off). Warn this many minutes before a block starts
+  #command: ... # optional, a shell command run with the warning, see the README
+
+#notifications:
+  #backend: osascript # optional, one of osascript (default), notify-send, command or none
+  #command: ... # for the command backend, see the README
+
+#hooks: # optional shell commands run on block events, see the README
+  #pre-start: ...
+  #on-start: ...
+  #on-fail: ...
+  #on-end: ...
+  #on-helper-cancelled: ...
+  #timeout-secs: 30 # optional, defaults to 30. Hooks running longer are killed
+
+#webhooks:
+  #url: http://localhost:8080/events # optional, block events are POSTed here as json
+  #max-attempts: 10 # optional, defaults to 10. Failed events are retried this many times
+
+#catch-up: # when a block's start was missed, eg while the Mac was asleep
+  #policy: remainder # optional, one of remainder (default), extend or skip, see the README
+  #grace-minutes: 2 # optional, defaults to 2. Starts later than this count as missed
+
+#metrics: # prometheus metrics, see the README
+  #textfile: /usr/local/var/node_exporter/ascrs.prom # optional, written after each --execute
+  #listen: 127.0.0.1:9877 # optional, localhost address --serve_metrics serves /metrics on
 
 blocks:
 - days: [Mon, Wed]
@@ -260,6 +885,109 @@ blocks:
 
 paths:
     #self-control: ... # optional, defaults to /Applications/SelfControl.app/Contents/MacOS/org.eyebeam.SelfControl
-    #launch-agents: ... # optional, defaults to ~/Library/LaunchAgents/"
+    #launch-agents: ... # optional, defaults to ~/Library/LaunchAgents/",
+        migrations::CURRENT_VERSION
+    )
+}
+
+fn build_example_toml_config() -> String {
+    format!(
+r#"version = {}
+#strict = true # optional, defaults to false. While a block is active, refuse config changes that
+               # remove, shorten or delay it until the block has ended
+#cooldown-hours = 24 # optional, defaults to 0. Changes that reduce blocked time only apply this
+                     # many hours after they were made
+#pause-notice-hours = 48 # optional, defaults to 24. How far in advance --pause must be requested
+#skips-per-week = 2 # optional, defaults to 0. How many upcoming blocks --skip_next may cancel a week
+#partner-public-key = "..." # optional. Changes that reduce blocked time must be signed with the
+                            # matching private key, see --sign_config
+#daemon = true # optional, defaults to false. --deploy installs a long-running --daemon instead of
+               # running --execute every 30 seconds
+
+[auto-password-input]
+#enable = false # optional, defaults to false
+#account-name = "..." # optional, defaults to $USER else $whoami
+
+#[block-warning]
+#minutes = 5 # optional, defaults to 0 (off). Warn this many minutes before a block starts
+#command = "..." # optional, a shell command run with the warning, see the README
+
+#[notifications]
+#backend = "osascript" # optional, one of osascript (default), notify-send, command or none
+#command = "..." # for the command backend, see the README
+
+#[hooks] # optional shell commands run on block events, see the README
+#pre-start = "..."
+#on-start = "..."
+#on-fail = "..."
+#on-end = "..."
+#on-helper-cancelled = "..."
+#timeout-secs = 30 # optional, defaults to 30. Hooks running longer are killed
+
+#[webhooks]
+#url = "http://localhost:8080/events" # optional, block events are POSTed here as json
+#max-attempts = 10 # optional, defaults to 10. Failed events are retried this many times
+
+#[catch-up] # when a block's start was missed, eg while the Mac was asleep
+#policy = "remainder" # optional, one of remainder (default), extend or skip, see the README
+#grace-minutes = 2 # optional, defaults to 2. Starts later than this count as missed
+
+#[metrics] # prometheus metrics, see the README
+#textfile = "/usr/local/var/node_exporter/ascrs.prom" # optional, written after each --execute
+#listen = "127.0.0.1:9877" # optional, localhost address --serve_metrics serves /metrics on
+
+[[blocks]]
+days = ["Mon", "Wed"]
+times = [["11:00", "13:00"], ["17:00", "19:30"]]
+
+[[blocks]]
+days = ["Thu"]
+times = [["21:00", "08:00"]] # This will block from Thursday 21:00 until Friday 08:00
+
+[[blocks]]
+days = ["All"]
+times = [["8:00", "9:00"]]
+# Explicitly defined weekdays override 'All'- eg Monday will not contain the 8:00 -> 9:00 block
+
+[paths]
+#self-control = "..." # optional, defaults to /Applications/SelfControl.app/Contents/MacOS/org.eyebeam.SelfControl
+#launch-agents = "..." # optional, defaults to ~/Library/LaunchAgents/"#,
+        migrations::CURRENT_VERSION
+    )
+}
+
+// json has no comments, see the yaml or toml example for an explanation of each field
+fn build_example_json_config() -> String {
+    format!(
+r#"{{
+  "version": {},
+  "strict": false,
+  "cooldown-hours": 0,
+  "pause-notice-hours": 24,
+  "skips-per-week": 0,
+  "daemon": false,
+  "auto-password-input": {{
+    "enable": false
+  }},
+  "block-warning": {{
+    "minutes": 0
+  }},
+  "notifications": {{
+    "backend": "osascript"
+  }},
+  "hooks": {{}},
+  "webhooks": {{}},
+  "catch-up": {{
+    "policy": "remainder"
+  }},
+  "metrics": {{}},
+  "blocks": [
+    {{ "days": ["Mon", "Wed"], "times": [["11:00", "13:00"], ["17:00", "19:30"]] }},
+    {{ "days": ["Thu"], "times": [["21:00", "08:00"]] }},
+    {{ "days": ["All"], "times": [["8:00", "9:00"]] }}
+  ],
+  "paths": {{}}
+}}"#,
+        migrations::CURRENT_VERSION
     )
 }
@@ -0,0 +1,311 @@
+// Commitment cooldown. With cooldown-hours set, config changes that reduce blocked time are queued
+// in pending-changes.json next to the config file, and only take effect cooldown-hours after they
+// were made- until then the previous schedule stays in force. Changes that add blocked time apply
+// immediately.
+//
+// Changes are classified per weekday, comparing the blocks in force on that day (so a weekday
+// falling back to All counts) before and after the change. Lowering cooldown-hours is itself a
+// change that reduces blocked time, and so are lowering pause-notice-hours, raising skips-per-week,
+// a catch-up setting that blocks less of a missed block (see CatchUp::is_weakened_by), and any
+// change to paths.self-control, which could point at something that never starts a block.
+//
+// The changes pending at each deploy are also kept in the deployed snapshot (see reload.rs), and
+// held back until they're due even if pending-changes.json is deleted.
+use chrono::{Duration, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    catch_up::CatchUp,
+    config::{Config, Day, WEEKDAYS},
+    utils::minute_ranges,
+    ResultE,
+};
+
+const PENDING_FILE: &str = "pending-changes.json";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum Change {
+    Blocks {
+        day: Weekday,
+        old_times: Vec<(NaiveTime, NaiveTime)>,
+        new_times: Vec<(NaiveTime, NaiveTime)>,
+    },
+    CooldownHours {
+        old_hours: u32,
+        new_hours: u32,
+    },
+    PauseNoticeHours {
+        old_hours: u32,
+        new_hours: u32,
+    },
+    SkipsPerWeek {
+        old_skips: u32,
+        new_skips: u32,
+    },
+    CatchUp {
+        old_catch_up: CatchUp,
+        new_catch_up: CatchUp,
+    },
+    SelfControlPath {
+        old_path: PathBuf,
+        new_path: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PendingChange {
+    id: u32,
+    change: Change,
+    queued_at: NaiveDateTime,
+    apply_at: NaiveDateTime,
+    // a cancelled change is never applied, the schedule before it stays in force for as long as
+    // the config file still contains the change
+    #[serde(default)]
+    cancelled: bool,
+}
+
+impl Display for PendingChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>3}  ", self.id)?;
+        match &self.change {
+            Change::Blocks {
+                day,
+                old_times,
+                new_times,
+            } => write!(f, "{}: {} -> {}", day, format_times(old_times), format_times(new_times))?,
+            Change::CooldownHours {
+                old_hours,
+                new_hours,
+            } => write!(f, "cooldown-hours: {} -> {}", old_hours, new_hours)?,
+            Change::PauseNoticeHours {
+                old_hours,
+                new_hours,
+            } => write!(f, "pause-notice-hours: {} -> {}", old_hours, new_hours)?,
+            Change::SkipsPerWeek {
+                old_skips,
+                new_skips,
+            } => write!(f, "skips-per-week: {} -> {}", old_skips, new_skips)?,
+            Change::CatchUp {
+                old_catch_up,
+                new_catch_up,
+            } => write!(f, "catch-up: {} -> {}", old_catch_up, new_catch_up)?,
+            Change::SelfControlPath { old_path, new_path } => write!(
+                f,
+                "paths.self-control: {} -> {}",
+                old_path.display(),
+                new_path.display()
+            )?,
+        }
+        match self.cancelled {
+            true => write!(f, "  (cancelled)"),
+            false => write!(f, "  (applies at {})", self.apply_at.format("%Y-%m-%d %H:%M")),
+        }
+    }
+}
+
+fn format_times(times: &[(NaiveTime, NaiveTime)]) -> String {
+    let times = times
+        .iter()
+        .map(|(start, end)| format!("[{}, {}]", start.format("%H:%M"), end.format("%H:%M")))
+        .collect::<Vec<_>>();
+    format!("[{}]", times.join(", "))
+}
+
+fn pending_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name(PENDING_FILE)
+}
+
+pub fn read_pending(config_path: &Path) -> ResultE<Vec<PendingChange>> {
+    let path = pending_path(config_path);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn write_pending(config_path: &Path, pending: &Vec<PendingChange>) -> ResultE<()> {
+    let path = pending_path(config_path);
+    if pending.is_empty() {
+        return match path.exists() {
+            true => fs::remove_file(path).map_err(|e| e.into()),
+            false => Ok(()),
+        };
+    }
+    fs::write(path, serde_json::to_string_pretty(pending)?)?;
+    Ok(())
+}
+
+pub fn cancel_pending(config_path: &Path, id: u32) -> ResultE<()> {
+    let mut pending = read_pending(config_path)?;
+    let change = pending
+        .iter_mut()
+        .find(|change| change.id == id)
+        .ok_or_else(|| format!("no pending change with id {}", id))?;
+    change.cancelled = true;
+    write_pending(config_path, &pending)
+}
+
+// when the next pending change that isn't cancelled takes effect
+pub fn next_apply(config_path: &Path) -> ResultE<Option<NaiveDateTime>> {
+    Ok(read_pending(config_path)?
+        .iter()
+        .filter(|change| !change.cancelled)
+        .map(|change| change.apply_at)
+        .min())
+}
+
+// keeps the schedule from before each pending change in force, dropping changes which are due
+pub fn apply_pending(config: &mut Config, config_path: &Path, now: NaiveDateTime) -> ResultE<()> {
+    let mut pending = read_pending(config_path)?;
+    let pending_count = pending.len();
+    pending.retain(|change| change.cancelled || change.apply_at > now);
+    if pending.len() != pending_count {
+        write_pending(config_path, &pending)?;
+    }
+    hold_back(config, &pending, now);
+    Ok(())
+}
+
+// keeps the schedule from before each of pending which isn't due yet in force
+pub fn hold_back(config: &mut Config, pending: &[PendingChange], now: NaiveDateTime) {
+    for pending_change in pending {
+        if !pending_change.cancelled && pending_change.apply_at <= now {
+            continue;
+        }
+        match &pending_change.change {
+            Change::Blocks { day, old_times, .. } => {
+                config.blocks.insert(Day::WeekDay(*day), old_times.clone());
+            }
+            Change::CooldownHours { old_hours, .. } => config.cooldown_hours = *old_hours,
+            Change::PauseNoticeHours { old_hours, .. } => config.pause_notice_hours = *old_hours,
+            Change::SkipsPerWeek { old_skips, .. } => config.skips_per_week = *old_skips,
+            Change::CatchUp { old_catch_up, .. } => config.catch_up = old_catch_up.clone(),
+            Change::SelfControlPath { old_path, .. } => config.paths.self_control = old_path.clone(),
+        }
+    }
+}
+
+// diffs the config in force against a new one, queueing the changes that reduce blocked time, and
+// dropping pending changes which the new config no longer makes
+pub fn queue_changes(
+    config_path: &Path,
+    in_force: &Config,
+    new: &Config,
+    now: NaiveDateTime,
+) -> ResultE<()> {
+    let mut pending = read_pending(config_path)?;
+    let cooldown = Duration::hours(in_force.cooldown_hours as i64);
+
+    let changes = weakening_changes(in_force, new);
+
+    // pending changes the new config doesn't make anymore apply immediately, ie are dropped
+    pending.retain(|pending_change| {
+        changes
+            .iter()
+            .any(|change| is_same_change(&pending_change.change, change))
+    });
+    if cooldown > Duration::zero() {
+        for change in changes {
+            // a pending change which is changed again restarts its cooldown
+            if pending.iter().any(|pending_change| pending_change.change == change) {
+                continue;
+            }
+            pending.retain(|pending_change| !is_same_change(&pending_change.change, &change));
+            let id = pending.iter().map(|change| change.id).max().unwrap_or(0) + 1;
+            pending.push(PendingChange {
+                id,
+                change,
+                queued_at: now,
+                apply_at: now + cooldown,
+                cancelled: false,
+            });
+        }
+    }
+    write_pending(config_path, &pending)
+}
+
+// the changes from in_force to new that reduce blocked time
+fn weakening_changes(in_force: &Config, new: &Config) -> Vec<Change> {
+    let mut changes = vec![];
+    for day in WEEKDAYS {
+        let old_times = in_force.get_blocks(day).cloned().unwrap_or_default();
+        let new_times = new.get_blocks(day).cloned().unwrap_or_default();
+        if !covers(&new_times, &old_times) {
+            changes.push(Change::Blocks {
+                day,
+                old_times,
+                new_times,
+            });
+        }
+    }
+    if new.cooldown_hours < in_force.cooldown_hours {
+        changes.push(Change::CooldownHours {
+            old_hours: in_force.cooldown_hours,
+            new_hours: new.cooldown_hours,
+        });
+    }
+    if new.pause_notice_hours < in_force.pause_notice_hours {
+        changes.push(Change::PauseNoticeHours {
+            old_hours: in_force.pause_notice_hours,
+            new_hours: new.pause_notice_hours,
+        });
+    }
+    if new.skips_per_week > in_force.skips_per_week {
+        changes.push(Change::SkipsPerWeek {
+            old_skips: in_force.skips_per_week,
+            new_skips: new.skips_per_week,
+        });
+    }
+    if in_force.catch_up.is_weakened_by(&new.catch_up) {
+        changes.push(Change::CatchUp {
+            old_catch_up: in_force.catch_up.clone(),
+            new_catch_up: new.catch_up.clone(),
+        });
+    }
+    if new.paths.self_control != in_force.paths.self_control {
+        changes.push(Change::SelfControlPath {
+            old_path: in_force.paths.self_control.clone(),
+            new_path: new.paths.self_control.clone(),
+        });
+    }
+    changes
+}
+
+pub fn reduces_blocked_time(in_force: &Config, new: &Config) -> bool {
+    !weakening_changes(in_force, new).is_empty()
+}
+
+// whether both changes are to the same weekday, or both to the same setting
+fn is_same_change(a: &Change, b: &Change) -> bool {
+    match (a, b) {
+        (Change::Blocks { day: a, .. }, Change::Blocks { day: b, .. }) => a == b,
+        (Change::CooldownHours { .. }, Change::CooldownHours { .. }) => true,
+        (Change::PauseNoticeHours { .. }, Change::PauseNoticeHours { .. }) => true,
+        (Change::SkipsPerWeek { .. }, Change::SkipsPerWeek { .. }) => true,
+        (Change::CatchUp { .. }, Change::CatchUp { .. }) => true,
+        (Change::SelfControlPath { .. }, Change::SelfControlPath { .. }) => true,
+        _ => false,
+    }
+}
+
+// whether every minute blocked by old_times is also blocked by new_times
+fn covers(new_times: &[(NaiveTime, NaiveTime)], old_times: &[(NaiveTime, NaiveTime)]) -> bool {
+    let blocked_minutes = |times: &[(NaiveTime, NaiveTime)]| {
+        let mut minutes = [false; 24 * 60];
+        for range in times.iter().flat_map(|times| minute_ranges(*times)) {
+            minutes[range.0 as usize..range.1 as usize].fill(true);
+        }
+        minutes
+    };
+    let (new_minutes, old_minutes) = (blocked_minutes(new_times), blocked_minutes(old_times));
+    old_minutes
+        .iter()
+        .zip(new_minutes.iter())
+        .all(|(old, new)| !old || *new)
+}
@@ -0,0 +1,190 @@
+// Daemon mode. --daemon runs --execute's work in one long-running process instead of a fresh one
+// every 30 seconds: it sleeps until the next block boundary (a block starting or ending, its
+// warning, SelfControl's block ending, a pause ending), then executes, at least every 5 minutes so
+// webhook retries and queued config changes aren't held up. It wakes early when the Mac wakes from
+// sleep, noticed by the wall clock jumping ahead of the monotonic clock which stops during sleep,
+// and when one of the config's files changes. The config is kept in memory between wakes, only
+// reloaded after one of those or once a queued change (see cooldown.rs) takes effect, and /metrics
+// is served alongside when metrics.listen is set (see metrics.rs).
+// With `daemon: true` in the config, --deploy installs it as a KeepAlive agent.
+use chrono::{Duration, Local, NaiveDateTime};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{self, Instant, SystemTime},
+};
+use tracing::{debug, error, info, warn};
+
+use super::{
+    begin_block,
+    config::{Config, ConfigError},
+    cooldown, layers, metrics, pause, reload, skips, ResultE,
+};
+
+// how often the clocks and config files are checked while sleeping
+const TICK_SECS: u64 = 5;
+// how far the wall clock may run ahead of the monotonic clock in a tick before it counts as a sleep
+const SLEEP_SLACK_SECS: u64 = 10;
+const MAX_SLEEP_MINUTES: i64 = 5;
+// how soon a failed execute is retried, as the agent run every 30 seconds would
+const RETRY_SECS: i64 = 30;
+
+enum Wake {
+    Due,
+    FromSleep,
+    ConfigChanged,
+}
+
+// the modification times of the config's files, to notice changes without reloading it
+struct ConfigFiles {
+    paths: Vec<PathBuf>,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl ConfigFiles {
+    fn read(config_path: &Path) -> Self {
+        let mut paths = layers::load(config_path)
+            .map_or(vec![config_path.to_path_buf()], |merged| merged.files);
+        // a file added to or removed from conf.d changes its modification time
+        paths.push(
+            config_path
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join(layers::CONF_D),
+        );
+        let modified = modified(&paths);
+        Self { paths, modified }
+    }
+
+    fn changed(&self) -> bool {
+        modified(&self.paths) != self.modified
+    }
+}
+
+fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+
+pub fn run(config_path: &Path) -> ResultE<()> {
+    info!("daemon started");
+    let mut config = reload::load_for_execute(config_path)?;
+    if config.metrics.listen.is_some() {
+        let config_path = config_path.to_path_buf();
+        thread::spawn(move || {
+            let result = reload::load_in_force(&config_path).and_then(|config| {
+                metrics::serve(&config.metrics, || reload::load_in_force(&config_path))
+            });
+            if let Err(e) = result {
+                error!("failed to serve metrics: {}", e);
+            }
+        });
+    }
+
+    loop {
+        let files = ConfigFiles::read(config_path);
+        // execute logs its own errors
+        let failed = super::execute(&config).is_err();
+
+        let now = Local::now().naive_local();
+        let selfcontrol_end = begin_block::cached_selfcontrol_block_end(&config, now)
+            .unwrap_or_else(|e| {
+                warn!("failed to check SelfControl: {}", e);
+                None
+            });
+        // as reload::load_for_execute finds pending changes, even once the config is deleted
+        let canonical_path = fs::canonicalize(config_path).unwrap_or(config_path.to_path_buf());
+        let pending_apply = cooldown::next_apply(&canonical_path).unwrap_or_else(|e| {
+            warn!("failed to read pending changes: {}", e);
+            None
+        });
+        let wake_at = next_wake(&config, now, selfcontrol_end, pending_apply, failed)?;
+        debug!("sleeping until {}", wake_at.format("%Y-%m-%d %H:%M:%S"));
+        let reload = match sleep_until(wake_at, &files) {
+            Wake::Due => pending_apply.is_some_and(|apply_at| apply_at <= Local::now().naive_local()),
+            Wake::FromSleep => {
+                info!("woke from sleep");
+                true
+            }
+            Wake::ConfigChanged => {
+                info!("{} has changed", config_path.display());
+                true
+            }
+        };
+        if !reload {
+            continue;
+        }
+
+        config = match reload::load_for_execute(config_path) {
+            Ok(config) => config,
+            // as for --execute, see reload::load_for_execute
+            Err(e) if e.downcast_ref::<ConfigError>().is_some_and(|e| e.newer_version) => {
+                return Err(e)
+            }
+            Err(e) => {
+                warn!(
+                    "failed to reload {}, keeping the config in memory: {}",
+                    config_path.display(),
+                    e
+                );
+                config
+            }
+        };
+    }
+}
+
+// the next time anything execute does could change
+fn next_wake(
+    config: &Config,
+    now: NaiveDateTime,
+    selfcontrol_end: Option<NaiveDateTime>,
+    pending_apply: Option<NaiveDateTime>,
+    failed: bool,
+) -> ResultE<NaiveDateTime> {
+    let latest = match failed {
+        true => now + Duration::seconds(RETRY_SECS),
+        false => now + Duration::minutes(MAX_SLEEP_MINUTES),
+    };
+    let mut boundaries = vec![];
+    boundaries.extend(config.get_active_block_end(now));
+    boundaries.extend(selfcontrol_end);
+    boundaries.extend(pending_apply);
+    if let Some((start, _)) = skips::next_block(config, now)? {
+        boundaries.push(start);
+        boundaries.push(start - Duration::minutes(config.block_warning.minutes as i64));
+    }
+    boundaries.extend(pause::upcoming_pauses(now)?.iter().map(|pause| pause.to));
+    Ok(boundaries
+        .into_iter()
+        .filter(|boundary| *boundary > now)
+        .fold(latest, NaiveDateTime::min))
+}
+
+fn sleep_until(wake_at: NaiveDateTime, files: &ConfigFiles) -> Wake {
+    loop {
+        let until_wake = (wake_at - Local::now().naive_local())
+            .to_std()
+            .unwrap_or_default();
+        if until_wake.is_zero() {
+            return Wake::Due;
+        }
+        let (wall_clock, monotonic_clock) = (SystemTime::now(), Instant::now());
+        thread::sleep(until_wake.min(time::Duration::from_secs(TICK_SECS)));
+        let wall_elapsed = wall_clock.elapsed().unwrap_or_default();
+        if wall_elapsed.saturating_sub(monotonic_clock.elapsed())
+            > time::Duration::from_secs(SLEEP_SLACK_SECS)
+        {
+            return Wake::FromSleep;
+        }
+        if files.changed() {
+            return Wake::ConfigChanged;
+        }
+    }
+}
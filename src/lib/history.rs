@@ -0,0 +1,306 @@
+// History of what --execute and block starts did, appended as json lines to history.jsonl in the
+// data dir. Records are only ever appended, and are read back with query, eg for stats and reports-
+// except that consecutive no-block runs are collapsed into one record, which they'd otherwise be
+// most of. Each record has a kind:
+//   execute: an --execute run, with the active block if there was one, and its outcome. Collapsed
+//            no-block runs have a count of runs and the time of the last one
+//   start-attempt: an attempt to start SelfControl until block_end
+//   selfcontrol-error: an error from SelfControl- including the password prompt being cancelled
+//                      (user-cancelled-helper), after which SelfControl is started again
+//   block-started: SelfControl was started, with the end date SelfControl reported for the block
+//   missed-block: a block's start was missed, eg while the Mac was asleep, see catch_up.rs
+use chrono::{Duration, Local, NaiveDateTime};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+use tracing::warn;
+
+use super::{catch_up::Policy, config, ResultE};
+
+const HISTORY_FILE: &str = "history.jsonl";
+// how long no-block executes may be apart to be collapsed into one record, a few missed runs
+const MAX_COLLAPSE_GAP_MINUTES: i64 = 10;
+const TAIL_BYTES: u64 = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Record {
+    pub time: NaiveDateTime,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Entry {
+    // execute is recorded every 30s, so leave out what's empty
+    Execute {
+        outcome: ExecuteOutcome,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        block_start: Option<NaiveDateTime>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        block_end: Option<NaiveDateTime>,
+        // for collapsed no-block runs, see record
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        runs: Option<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_run: Option<NaiveDateTime>,
+    },
+    StartAttempt {
+        block_end: NaiveDateTime,
+    },
+    SelfcontrolError {
+        error: SelfControlErrorKind,
+        message: String,
+    },
+    BlockStarted {
+        block_end: NaiveDateTime,
+        selfcontrol_end: Option<NaiveDateTime>,
+    },
+    MissedBlock {
+        block_start: NaiveDateTime,
+        block_end: NaiveDateTime,
+        missed_minutes: i64,
+        policy: Policy,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExecuteOutcome {
+    NoBlock,
+    Paused,
+    Skipped,
+    AlreadyActive,
+    Started,
+    Failed,
+    // the block's start was missed, and catch-up.policy is skip
+    MissedSkipped,
+}
+
+// mirrors begin_block's SelfControlError, without its details
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelfControlErrorKind {
+    UserCancelledHelper,
+    NoInputTimeout,
+    NoSuccessMsg,
+    CommandError,
+    ParseError,
+}
+
+impl SelfControlErrorKind {
+    pub const ALL: [SelfControlErrorKind; 5] = [
+        SelfControlErrorKind::UserCancelledHelper,
+        SelfControlErrorKind::NoInputTimeout,
+        SelfControlErrorKind::NoSuccessMsg,
+        SelfControlErrorKind::CommandError,
+        SelfControlErrorKind::ParseError,
+    ];
+}
+
+impl Display for SelfControlErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SelfControlErrorKind::UserCancelledHelper => "user-cancelled-helper",
+            SelfControlErrorKind::NoInputTimeout => "no-input-timeout",
+            SelfControlErrorKind::NoSuccessMsg => "no-success-msg",
+            SelfControlErrorKind::CommandError => "command-error",
+            SelfControlErrorKind::ParseError => "parse-error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn history_path() -> ResultE<PathBuf> {
+    Ok(config::data_dir()?.join(HISTORY_FILE))
+}
+
+// appends entry, logging rather than returning any error- history is never worth failing a block.
+// A no-block execute right after another (at most MAX_COLLAPSE_GAP_MINUTES later) is collapsed
+// into the last record instead, so a gap in the runs, eg while the agent wasn't running, still shows
+pub fn record(entry: Entry) {
+    let record = Record {
+        time: Local::now().naive_local(),
+        entry,
+    };
+    let result = (|| -> ResultE<()> {
+        fs::create_dir_all(config::data_dir()?)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(history_path()?)?;
+        // held until file is dropped, as runs of --execute and --daemon may overlap
+        file.lock_exclusive()?;
+        append(&mut file, record)
+    })();
+    if let Err(e) = result {
+        warn!("failed to record history: {}", e);
+    }
+}
+
+fn append(file: &mut File, record: Record) -> ResultE<()> {
+    let record = match is_no_block(&record.entry) {
+        true => collapse_into_last(file, record)?,
+        false => record,
+    };
+    file.seek(SeekFrom::End(0))?;
+    writeln!(file, "{}", serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+fn is_no_block(entry: &Entry) -> bool {
+    matches!(
+        entry,
+        Entry::Execute {
+            outcome: ExecuteOutcome::NoBlock,
+            ..
+        }
+    )
+}
+
+// record, merged into the last record if that's a recent no-block execute too, which is then cut
+// off the end of the file to be written again
+fn collapse_into_last(file: &mut File, record: Record) -> ResultE<Record> {
+    // records are short, so the last one is within the file's last TAIL_BYTES
+    let length = file.metadata()?.len();
+    let tail_start = length.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = vec![];
+    file.read_to_end(&mut tail)?;
+    let tail_end = tail.iter().rposition(|byte| *byte != b'\n').map_or(0, |last| last + 1);
+    let last_line_start = tail[..tail_end]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    // a line cut short by the start of the tail doesn't parse either
+    let last_line = &tail[last_line_start..tail_end];
+    let Ok(last) = serde_json::from_slice::<Record>(last_line) else { return Ok(record) };
+
+    let Entry::Execute {
+        outcome: ExecuteOutcome::NoBlock,
+        runs,
+        last_run,
+        ..
+    } = last.entry
+    else {
+        return Ok(record);
+    };
+    if record.time - last_run.unwrap_or(last.time) > Duration::minutes(MAX_COLLAPSE_GAP_MINUTES) {
+        return Ok(record);
+    }
+    file.set_len(tail_start + last_line_start as u64)?;
+    Ok(Record {
+        time: last.time,
+        entry: Entry::Execute {
+            outcome: ExecuteOutcome::NoBlock,
+            block_start: None,
+            block_end: None,
+            runs: Some(runs.unwrap_or(1) + 1),
+            last_run: Some(record.time),
+        },
+    })
+}
+
+// the records from from until to, oldest first
+pub fn query(from: NaiveDateTime, to: NaiveDateTime) -> ResultE<Vec<Record>> {
+    let path = history_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let mut records = vec![];
+    for line in fs::read_to_string(path)?.lines() {
+        // a line cut short, eg by a crash while it was written, is skipped
+        let Ok(record) = serde_json::from_str::<Record>(line) else { continue };
+        if record.time >= from && record.time < to {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::env;
+
+    fn execute(outcome: ExecuteOutcome, minute: u32) -> Record {
+        Record {
+            time: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().and_hms_opt(9, minute, 0).unwrap(),
+            entry: Entry::Execute {
+                outcome,
+                block_start: None,
+                block_end: None,
+                runs: None,
+                last_run: None,
+            },
+        }
+    }
+
+    // appends each to a scratch file, returning the file's records
+    fn record_all(name: &str, records: Vec<Record>) -> Vec<Record> {
+        let path = env::temp_dir().join(format!("ascrs-history-{}-{}", name, std::process::id()));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        for record in records {
+            append(&mut file, record).unwrap();
+        }
+        let records = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        fs::remove_file(path).unwrap();
+        records
+    }
+
+    fn runs(record: &Record) -> (Option<u32>, Option<NaiveDateTime>) {
+        match record.entry {
+            Entry::Execute { runs, last_run, .. } => (runs, last_run),
+            _ => panic!("not an execute record"),
+        }
+    }
+
+    #[test]
+    fn collapses_consecutive_no_block_runs() {
+        let records = record_all(
+            "collapse",
+            vec![
+                execute(ExecuteOutcome::NoBlock, 0),
+                execute(ExecuteOutcome::NoBlock, 1),
+                execute(ExecuteOutcome::NoBlock, 2),
+            ],
+        );
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].time, execute(ExecuteOutcome::NoBlock, 0).time);
+        assert_eq!(runs(&records[0]), (Some(3), Some(execute(ExecuteOutcome::NoBlock, 2).time)));
+    }
+
+    #[test]
+    fn other_records_and_gaps_end_a_collapsed_run() {
+        let records = record_all(
+            "gaps",
+            vec![
+                execute(ExecuteOutcome::NoBlock, 0),
+                execute(ExecuteOutcome::AlreadyActive, 1),
+                execute(ExecuteOutcome::NoBlock, 2),
+                // the agent wasn't running for longer than MAX_COLLAPSE_GAP_MINUTES
+                execute(ExecuteOutcome::NoBlock, 30),
+            ],
+        );
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|record| runs(record) == (None, None)));
+    }
+}
@@ -0,0 +1,183 @@
+// User hook scripts, run on block lifecycle events with sh -c:
+//   pre-start: just before SelfControl is started
+//   on-start: the block has started
+//   on-fail: the block failed to start
+//   on-helper-cancelled: SelfControl's password prompt was cancelled, it will be shown again
+//   on-end: the block has ended- run by the first --execute after it ended, as nothing of ours
+//           runs during a block. The end of the last started block is kept in started-block.json
+//           in the state dir for this (and for the block-ended webhook)
+// Event details are passed in ASCRS_EVENT, ASCRS_BLOCK_END and ASCRS_ERROR (on-fail only), and as
+// json on stdin. A hook still running after hooks.timeout-secs is killed. Hooks never stop a block,
+// their exit statuses are only logged.
+use chrono::{Local, NaiveDateTime};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    process::{Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use super::{config, ResultE};
+
+const STARTED_BLOCK_FILE: &str = "started-block.json";
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Hooks {
+    pre_start: Option<String>,
+    on_start: Option<String>,
+    on_fail: Option<String>,
+    on_end: Option<String>,
+    on_helper_cancelled: Option<String>,
+    timeout_secs: u64,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            pre_start: None,
+            on_start: None,
+            on_fail: None,
+            on_end: None,
+            on_helper_cancelled: None,
+            timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Event {
+    PreStart,
+    OnStart,
+    OnFail,
+    OnEnd,
+    OnHelperCancelled,
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::PreStart => "pre-start",
+            Event::OnStart => "on-start",
+            Event::OnFail => "on-fail",
+            Event::OnEnd => "on-end",
+            Event::OnHelperCancelled => "on-helper-cancelled",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EventDetails<'a> {
+    event: &'static str,
+    time: NaiveDateTime,
+    block_end: NaiveDateTime,
+    error: Option<&'a str>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StartedBlock {
+    block_end: NaiveDateTime,
+}
+
+pub fn run(hooks: &Hooks, event: Event, block_end: NaiveDateTime, error: Option<&str>) {
+    let command = match event {
+        Event::PreStart => &hooks.pre_start,
+        Event::OnStart => &hooks.on_start,
+        Event::OnFail => &hooks.on_fail,
+        Event::OnEnd => &hooks.on_end,
+        Event::OnHelperCancelled => &hooks.on_helper_cancelled,
+    };
+    let Some(command) = command else { return };
+
+    let details = EventDetails {
+        event: event.name(),
+        time: Local::now().naive_local(),
+        block_end,
+        error,
+    };
+    match run_command(command, &details, Duration::from_secs(hooks.timeout_secs)) {
+        Ok(Some(status)) if status.success() => info!("hook {}: {}", event.name(), status),
+        Ok(Some(status)) => warn!("hook {}: {}", event.name(), status),
+        Ok(None) => warn!(
+            "hook {}: killed after timing out ({}s)",
+            event.name(),
+            hooks.timeout_secs
+        ),
+        Err(e) => warn!("hook {}: failed to run: {}", event.name(), e),
+    }
+}
+
+// the exit status of the hook, or None if it timed out and was killed
+fn run_command(
+    command: &str,
+    details: &EventDetails,
+    timeout: Duration,
+) -> ResultE<Option<ExitStatus>> {
+    let block_end = details.block_end.format("%Y-%m-%dT%H:%M:%S").to_string();
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("ASCRS_EVENT", details.event)
+        .env("ASCRS_BLOCK_END", block_end)
+        .env("ASCRS_ERROR", details.error.unwrap_or_default())
+        .stdin(Stdio::piped())
+        .spawn()?;
+    // a hook which doesn't read stdin closes it early, which isn't an error
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(serde_json::to_string(details)?.as_bytes());
+    }
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// remembers a started block, for on-end, logging rather than returning any error
+pub fn record_started_block(block_end: NaiveDateTime) {
+    let result = (|| -> ResultE<()> {
+        fs::create_dir_all(config::state_dir()?)?;
+        fs::write(
+            config::state_dir()?.join(STARTED_BLOCK_FILE),
+            serde_json::to_string(&StartedBlock { block_end })?,
+        )?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        warn!("failed to record the started block for on-end: {}", e);
+    }
+}
+
+// the end of the last started block if it has ended since, for on-end, logging rather than
+// returning any error
+pub fn take_ended_block(now: NaiveDateTime) -> Option<NaiveDateTime> {
+    let result = (|| -> ResultE<Option<NaiveDateTime>> {
+        let path = config::state_dir()?.join(STARTED_BLOCK_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        // unreadable, it's dropped rather than failing every later run
+        let started_block = serde_json::from_str::<StartedBlock>(&fs::read_to_string(&path)?);
+        if started_block.as_ref().is_ok_and(|started_block| started_block.block_end > now) {
+            return Ok(None);
+        }
+        fs::remove_file(path)?;
+        Ok(Some(started_block?.block_end))
+    })();
+    result.unwrap_or_else(|e| {
+        warn!("failed to read the started block for on-end: {}", e);
+        None
+    })
+}
@@ -0,0 +1,260 @@
+// Layering of config files. A config file may pull in other files, and be overridden per host:
+//
+//   include: [shared/team.yaml]   # relative to the including file
+//   hosts:
+//     work-laptop:                # matched against `hostname`, with or without its domain
+//       paths: ...
+//
+// Files are merged in this order, later files taking precedence:
+//   1. each included file (recursively, in the order listed), then the including file itself
+//   2. every conf.d/*.{yaml,toml,json} next to the main config file, in filename order
+//   3. the `hosts` section matching this machine from each of the above files, in the same order
+//
+// Merge semantics:
+//   - blocks: a later entry replaces all earlier times of each day it lists, days it doesn't
+//     list are kept- eg a later `days: [Mon]` entry removes Mon from an earlier `days: [Mon, Wed]`
+//   - tables (paths, auto-password-input, hooks etc, see TABLES): merged key by key, later keys win
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::{
+    config::{ConfigError, ConfigFormat, Day, ParseError},
+    migrations,
+};
+
+pub const CONF_D: &str = "conf.d";
+// config keys whose values are merged key by key
+const TABLES: [&str; 8] = [
+    "paths",
+    "auto-password-input",
+    "block-warning",
+    "notifications",
+    "hooks",
+    "webhooks",
+    "catch-up",
+    "metrics",
+];
+
+pub struct MergedConfig {
+    pub value: Value,
+    // the contents of the config file if no layering was used, so it can be parsed directly and
+    // errors keep their line and column
+    pub single_file: Option<(String, ConfigFormat)>,
+    // every file merged, eg to watch for changes
+    pub files: Vec<PathBuf>,
+    // the merge before host overrides, with every file's hosts section kept in a list- the same on
+    // every machine, for signing
+    pub host_independent: Value,
+}
+
+struct Layer {
+    path: PathBuf,
+    value: Map<String, Value>,
+    host_override: Option<Map<String, Value>>,
+    // the whole hosts section, see MergedConfig::host_independent
+    hosts: Option<Value>,
+    contents: String,
+    format: ConfigFormat,
+    // whether the file can be parsed as a Config as is: it's up to date and uses neither include
+    // nor hosts
+    is_plain: bool,
+}
+
+pub fn load(config_path: &Path) -> Result<MergedConfig, ConfigError> {
+    let mut layers = vec![];
+    load_file(config_path, &mut vec![], &mut layers)?;
+
+    let conf_d = config_path.parent().unwrap_or(Path::new(".")).join(CONF_D);
+    for path in conf_d_files(&conf_d).map_err(|e| ConfigError::new(&conf_d, e))? {
+        load_file(&path, &mut vec![], &mut layers)?;
+    }
+
+    let single_file = match layers.as_slice() {
+        [layer] if layer.is_plain => Some((layer.contents.clone(), layer.format)),
+        _ => None,
+    };
+
+    let files = layers.iter().map(|layer| layer.path.clone()).collect();
+    let mut merged = Map::new();
+    let mut host_overrides = vec![];
+    let mut hosts = vec![];
+    for layer in layers {
+        merge(&mut merged, layer.value);
+        host_overrides.extend(layer.host_override);
+        hosts.extend(layer.hosts);
+    }
+    let mut host_independent = merged.clone();
+    host_independent.insert("hosts".to_string(), Value::Array(hosts));
+    for host_override in host_overrides {
+        merge(&mut merged, host_override);
+    }
+
+    Ok(MergedConfig {
+        value: Value::Object(merged),
+        single_file,
+        files,
+        host_independent: Value::Object(host_independent),
+    })
+}
+
+fn load_file(
+    path: &Path,
+    include_stack: &mut Vec<PathBuf>,
+    layers: &mut Vec<Layer>,
+) -> Result<(), ConfigError> {
+    let canonical_path = fs::canonicalize(path).map_err(|e| ConfigError::new(path, e.into()))?;
+    if include_stack.contains(&canonical_path) {
+        return Err(ConfigError::new(path, "config file includes itself".into()));
+    }
+
+    let format = ConfigFormat::from_path(path).map_err(|e| ConfigError::new(path, e.into()))?;
+    let contents = fs::read_to_string(path).map_err(|e| ConfigError::new(path, e.into()))?;
+    let mut value = parse_value(&contents, format).map_err(|e| ConfigError::new(path, e))?;
+    let version = migrations::migrate(&mut value).map_err(|e| ConfigError::from_migration(path, e))?;
+    // a yaml table with only commented out keys is null, which is an empty table
+    for table in TABLES {
+        if let Some(table @ Value::Null) = value.get_mut(table) {
+            *table = Value::Object(Map::new());
+        }
+    }
+
+    let is_plain = version == migrations::CURRENT_VERSION
+        && !value.contains_key("include")
+        && !value.contains_key("hosts");
+    let includes = match value.remove("include") {
+        None => vec![],
+        Some(Value::Array(includes)) => includes,
+        Some(_) => return Err(ConfigError::new(path, "include: expected a list of paths".into())),
+    };
+
+    include_stack.push(canonical_path.clone());
+    for include in includes {
+        let Value::String(include) = include else {
+            return Err(ConfigError::new(path, "include: expected a list of paths".into()));
+        };
+        let include = path.parent().unwrap_or(Path::new(".")).join(include);
+        load_file(&include, include_stack, layers)?;
+    }
+    include_stack.pop();
+
+    let hosts = value.remove("hosts");
+    let host_override = match hosts.clone() {
+        None => None,
+        Some(Value::Object(mut hosts)) => match hostname() {
+            Some(hostname) => find_host(&mut hosts, &hostname)
+                .map(|host_override| match host_override {
+                    Value::Object(host_override) => Ok(host_override),
+                    _ => Err(ConfigError::new(
+                        path,
+                        format!("hosts.{}: expected a mapping", hostname).into(),
+                    )),
+                })
+                .transpose()?,
+            None => None,
+        },
+        Some(_) => return Err(ConfigError::new(path, "hosts: expected a mapping".into())),
+    };
+
+    layers.push(Layer {
+        path: canonical_path,
+        value,
+        host_override,
+        hosts,
+        contents,
+        format,
+        is_plain,
+    });
+    Ok(())
+}
+
+pub fn parse_value(contents: &str, format: ConfigFormat) -> Result<Map<String, Value>, ParseError> {
+    let value: Value = match format {
+        ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(ParseError::from_yaml)?,
+        ConfigFormat::Toml => {
+            toml::from_str(contents).map_err(|e| ParseError::from_toml(e, contents))?
+        }
+        ConfigFormat::Json => serde_json::from_str(contents).map_err(ParseError::from_json)?,
+    };
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err("expected a mapping at the top level of the config file".into()),
+    }
+}
+
+fn conf_d_files(conf_d: &Path) -> Result<Vec<PathBuf>, ParseError> {
+    if !conf_d.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut paths = fs::read_dir(conf_d)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.is_file() && ConfigFormat::from_path(path).is_ok());
+    paths.sort();
+    Ok(paths)
+}
+
+// hosts may be keyed by the full hostname (eg work-laptop.local) or without its domain
+fn find_host(hosts: &mut Map<String, Value>, hostname: &str) -> Option<Value> {
+    let short_hostname = hostname.split('.').next().unwrap_or(hostname);
+    hosts
+        .remove(hostname)
+        .or_else(|| hosts.remove(short_hostname))
+}
+
+fn hostname() -> Option<String> {
+    let output = Command::new("hostname").output().ok()?;
+    let hostname = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!hostname.is_empty()).then_some(hostname)
+}
+
+fn merge(base: &mut Map<String, Value>, layer: Map<String, Value>) {
+    for (key, value) in layer {
+        match (key.as_str(), base.get_mut(&key), value) {
+            ("blocks", Some(Value::Array(base_blocks)), Value::Array(blocks)) => {
+                merge_blocks(base_blocks, blocks)
+            }
+            (key, Some(Value::Object(base_table)), Value::Object(table)) if TABLES.contains(&key) => {
+                base_table.extend(table)
+            }
+            // eg a yaml table with only commented out keys, leave earlier values alone
+            (_, Some(_), Value::Null) => (),
+            (_, _, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_blocks(base_blocks: &mut Vec<Value>, blocks: Vec<Value>) {
+    // compare parsed days, so eg Mon and Monday are treated as the same day
+    let parse_days = |entry: &Value| {
+        entry
+            .get("days")
+            .and_then(Value::as_array)
+            .map(|days| {
+                days.iter()
+                    .filter_map(|day| serde_json::from_value::<Day>(day.clone()).ok())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    let overridden_days = blocks.iter().flat_map(parse_days).collect::<Vec<_>>();
+    for entry in base_blocks.iter_mut() {
+        if let Some(Value::Array(days)) = entry.get_mut("days") {
+            days.retain(|day| match serde_json::from_value::<Day>(day.clone()) {
+                Ok(day) => !overridden_days.contains(&day),
+                Err(_) => true,
+            });
+        }
+    }
+    base_blocks.retain(|entry| match entry.get("days") {
+        Some(Value::Array(days)) => !days.is_empty(),
+        _ => true,
+    });
+    base_blocks.extend(blocks);
+}
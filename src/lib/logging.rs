@@ -0,0 +1,107 @@
+// Logging, with tracing. Events are written to ascrs.log in the state dir, and to stderr. The
+// launch agent's stdout and stderr go to the same log file (see plist.rs), so anything printed or a
+// panic under launchd ends up there too- in that case events are only written to the log file once.
+// The level is info, raised by -v (debug, -vv trace) or lowered by -q (warn, -qq error).
+// The log is rotated when a run finds it over 1MB: ascrs.log becomes ascrs.log.1, ascrs.log.1
+// becomes ascrs.log.2 and so on, keeping 5 old logs.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, IsTerminal},
+    os::{fd::AsFd, unix::fs::MetadataExt},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use chrono::Local;
+use tracing::{level_filters::LevelFilter, warn};
+use tracing_subscriber::{
+    fmt::{self, format::Writer, time::FormatTime},
+    prelude::*,
+};
+
+use super::{config, ResultE};
+
+const LOG_FILE: &str = "ascrs.log";
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+const OLD_LOGS: u32 = 5;
+
+pub fn log_path() -> ResultE<PathBuf> {
+    Ok(config::state_dir()?.join(LOG_FILE))
+}
+
+pub fn init(verbose: u8, quiet: u8) -> ResultE<()> {
+    let level = match verbose as i16 - quiet as i16 {
+        i16::MIN..=-2 => LevelFilter::ERROR,
+        -1 => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+
+    let log_path = log_path()?;
+    // checked before rotating, as launchd opened the log for this run before it was moved
+    let stderr_is_log = is_stderr(&log_path);
+    let log_file = open_log(&log_path);
+
+    let stderr_layer = (!stderr_is_log).then(|| {
+        fmt::layer()
+            .with_writer(io::stderr)
+            .with_ansi(io::stderr().is_terminal())
+            .with_target(false)
+            .without_time()
+    });
+    let file_layer = log_file.as_ref().ok().and_then(|file| {
+        let file = file.try_clone().ok()?;
+        Some(
+            fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_timer(LocalTime),
+        )
+    });
+    tracing_subscriber::registry()
+        .with(stderr_layer)
+        .with(file_layer)
+        .with(level)
+        .try_init()?;
+
+    if let Err(e) = log_file {
+        warn!("can't write to {}: {}", log_path.display(), e);
+    }
+    Ok(())
+}
+
+// local times, like the rest of our files
+struct LocalTime;
+
+impl FormatTime for LocalTime {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        write!(w, "{}", Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"))
+    }
+}
+
+fn open_log(log_path: &Path) -> ResultE<File> {
+    fs::create_dir_all(config::state_dir()?)?;
+    if fs::metadata(log_path).is_ok_and(|metadata| metadata.len() > MAX_LOG_BYTES) {
+        rotate(log_path)?;
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(log_path)?)
+}
+
+fn rotate(log_path: &Path) -> ResultE<()> {
+    let old_log = |n: u32| PathBuf::from(format!("{}.{}", log_path.display(), n));
+    for n in (1..OLD_LOGS).rev() {
+        if old_log(n).exists() {
+            fs::rename(old_log(n), old_log(n + 1))?;
+        }
+    }
+    fs::rename(log_path, old_log(1))?;
+    Ok(())
+}
+
+// whether stderr is the file at path, as it is when launchd runs the agent
+fn is_stderr(path: &Path) -> bool {
+    let Ok(log) = fs::metadata(path) else { return false };
+    let Ok(stderr) = io::stderr().as_fd().try_clone_to_owned() else { return false };
+    let Ok(stderr) = File::from(stderr).metadata() else { return false };
+    stderr.dev() == log.dev() && stderr.ino() == log.ino()
+}
@@ -0,0 +1,229 @@
+// Prometheus metrics. With metrics.textfile set, --execute writes them to that file after each run,
+// for node_exporter's textfile collector. With metrics.listen set, --daemon (or --serve_metrics
+// without it) serves them on http://<metrics.listen>/metrics, computed when scraped- only on
+// localhost, as there's no authentication. The attempt and failure counters are kept in
+// metrics-counters.json in the state dir, rather than counted from the whole history on every run.
+use chrono::{Local, NaiveDateTime, TimeZone};
+use fs2::FileExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+use super::{
+    begin_block,
+    config::{self, Config},
+    history::SelfControlErrorKind,
+    skips, ResultE,
+};
+
+const COUNTERS_FILE: &str = "metrics-counters.json";
+const READ_TIMEOUT_SECS: u64 = 5;
+
+#[derive(Debug, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Metrics {
+    #[serde(default)]
+    textfile: Option<PathBuf>,
+    #[serde(default)]
+    pub listen: Option<String>,
+}
+
+pub enum Counter {
+    ActivationAttempt,
+    Failure(SelfControlErrorKind),
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Counters {
+    activation_attempts: u64,
+    failures: BTreeMap<SelfControlErrorKind, u64>,
+}
+
+// the metrics.listen address if it's set, which must be on localhost
+pub fn listen_address(metrics: &Metrics) -> ResultE<Option<SocketAddr>> {
+    let Some(listen) = &metrics.listen else { return Ok(None) };
+    let address: SocketAddr = listen
+        .parse()
+        .map_err(|e| format!("metrics.listen: {}: {}", listen, e))?;
+    if !address.ip().is_loopback() {
+        return Err(format!(
+            "metrics.listen: {} isn't a localhost address, /metrics has no authentication",
+            listen
+        )
+        .into());
+    }
+    Ok(Some(address))
+}
+
+// counts counter, logging rather than returning any error
+pub fn count(counter: Counter) {
+    let result = (|| -> ResultE<()> {
+        fs::create_dir_all(config::state_dir()?)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(config::state_dir()?.join(COUNTERS_FILE))?;
+        // held until file is dropped
+        file.lock_exclusive()?;
+        let mut json = String::new();
+        file.read_to_string(&mut json)?;
+        let mut counters: Counters = match json.is_empty() {
+            true => Counters::default(),
+            false => serde_json::from_str(&json)?,
+        };
+        match counter {
+            Counter::ActivationAttempt => counters.activation_attempts += 1,
+            Counter::Failure(kind) => *counters.failures.entry(kind).or_insert(0) += 1,
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(&counters)?.as_bytes())?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        warn!("failed to count metric: {}", e);
+    }
+}
+
+fn read_counters() -> ResultE<Counters> {
+    let path = config::state_dir()?.join(COUNTERS_FILE);
+    match path.exists() {
+        true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+        false => Ok(Counters::default()),
+    }
+}
+
+fn timestamp(time: NaiveDateTime) -> i64 {
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map_or(0, |time| time.timestamp())
+}
+
+// the metrics in prometheus' text format
+pub fn render(config: &Config, now: NaiveDateTime) -> ResultE<String> {
+    let selfcontrol_end = begin_block::selfcontrol_block_end(config)?;
+    let seconds_remaining = selfcontrol_end.map_or(0, |end| (end - now).num_seconds().max(0));
+    let next_block_start = skips::next_block(config, now)?.map_or(0, |(start, _)| timestamp(start));
+    let counters = read_counters()?;
+
+    let mut metrics = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, i64)>| {
+        let _ = writeln!(metrics, "# HELP {} {}", name, help);
+        let _ = writeln!(metrics, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(metrics, "{}{} {}", name, labels, value);
+        }
+    };
+    metric(
+        "ascrs_block_active",
+        "gauge",
+        "Whether a SelfControl block is running.",
+        vec![(String::new(), selfcontrol_end.is_some() as i64)],
+    );
+    metric(
+        "ascrs_block_seconds_remaining",
+        "gauge",
+        "Seconds until the running SelfControl block ends, 0 without one.",
+        vec![(String::new(), seconds_remaining)],
+    );
+    metric(
+        "ascrs_next_block_start_timestamp_seconds",
+        "gauge",
+        "Unix time the next scheduled block starts, 0 without one in the next week.",
+        vec![(String::new(), next_block_start)],
+    );
+    metric(
+        "ascrs_activation_attempts_total",
+        "counter",
+        "Attempts to start SelfControl.",
+        vec![(String::new(), counters.activation_attempts as i64)],
+    );
+    metric(
+        "ascrs_activation_failures_total",
+        "counter",
+        "SelfControl errors, by kind.",
+        SelfControlErrorKind::ALL
+            .iter()
+            .map(|kind| {
+                let count = counters.failures.get(kind).copied().unwrap_or(0);
+                (format!("{{kind=\"{}\"}}", kind), count as i64)
+            })
+            .collect(),
+    );
+    metric(
+        "ascrs_last_run_timestamp_seconds",
+        "gauge",
+        "Unix time the metrics were last computed.",
+        vec![(String::new(), timestamp(now))],
+    );
+    Ok(metrics)
+}
+
+// writes the metrics to metrics.textfile if it's set, logging rather than returning any error
+pub fn write_textfile(config: &Config, now: NaiveDateTime) {
+    let Some(textfile) = &config.metrics.textfile else { return };
+    let result = (|| -> ResultE<()> {
+        // written whole then renamed, so the collector never reads half a file
+        let temporary = textfile.with_extension("prom.tmp");
+        fs::write(&temporary, render(config, now)?)?;
+        fs::rename(&temporary, textfile)?;
+        Ok(())
+    })();
+    if let Err(e) = result {
+        warn!("failed to write {}: {}", textfile.display(), e);
+    }
+}
+
+// serves /metrics on metrics.listen until the process is stopped, with the config config() returns
+pub fn serve(metrics: &Metrics, config: impl Fn() -> ResultE<Config>) -> ResultE<()> {
+    let address = listen_address(metrics)?.ok_or("metrics.listen isn't set")?;
+    let listener = TcpListener::bind(address)?;
+    info!("serving metrics on http://{}/metrics", address);
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(|e| e.into())
+            .and_then(|stream| respond(stream, &config));
+        if let Err(e) = result {
+            warn!("metrics: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, config: &impl Fn() -> ResultE<Config>) -> ResultE<()> {
+    // requests are answered one at a time, so a client that never sends one mustn't hold up others
+    stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    debug!("metrics: {}", request_line.trim());
+
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => match config().and_then(|config| render(&config, Local::now().naive_local())) {
+            Ok(metrics) => ("200 OK", metrics),
+            Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+        },
+        ["GET", _] => ("404 Not Found", "see /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
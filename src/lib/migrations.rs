@@ -0,0 +1,111 @@
+// Config files carry a version, so the format can change without silently breaking deployed
+// agents. Files without one are version 0. Older files are migrated in memory each time they are
+// loaded, and can be rewritten on disk with --migrate_config.
+use serde_json::{Map, Value};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+pub const CURRENT_VERSION: u64 = 1;
+
+// MIGRATIONS[n] upgrades a version n config to version n + 1
+const MIGRATIONS: [fn(&mut Map<String, Value>); CURRENT_VERSION as usize] = [v0_to_v1];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MigrationError {
+    InvalidVersion,
+    // the config needs a newer auto-selfcontrol-rs, which --execute must refuse rather than fall
+    // back from, see reload.rs
+    NewerVersion(u64),
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::InvalidVersion => write!(f, "version: expected a non-negative integer"),
+            MigrationError::NewerVersion(version) => write!(
+                f,
+                "config version {} is newer than the latest version this auto-selfcontrol-rs \
+                supports ({}), refusing to use it- upgrade auto-selfcontrol-rs",
+                version, CURRENT_VERSION
+            ),
+        }
+    }
+}
+impl Error for MigrationError {}
+
+// upgrades config to CURRENT_VERSION, returning the version it had before
+pub fn migrate(config: &mut Map<String, Value>) -> Result<u64, MigrationError> {
+    let version = match config.get("version") {
+        None => 0,
+        Some(version) => version.as_u64().ok_or(MigrationError::InvalidVersion)?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(MigrationError::NewerVersion(version));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(config);
+    }
+    config.insert("version".to_string(), CURRENT_VERSION.into());
+    Ok(version)
+}
+
+// version 0 example configs documented auto-password-input.account_name, which was rejected as
+// an unknown field- the field has always been account-name
+fn v0_to_v1(config: &mut Map<String, Value>) {
+    if let Some(Value::Object(auto_password_input)) = config.get_mut("auto-password-input") {
+        if let Some(account_name) = auto_password_input.remove("account_name") {
+            auto_password_input
+                .entry("account-name")
+                .or_insert(account_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(config) => config,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn migrates_v0_account_name() {
+        let mut v0 = config(json!({"auto-password-input": {"enable": true, "account_name": "me"}}));
+        assert_eq!(migrate(&mut v0), Ok(0));
+        assert_eq!(
+            Value::Object(v0),
+            json!({"version": 1, "auto-password-input": {"enable": true, "account-name": "me"}})
+        );
+    }
+
+    #[test]
+    fn leaves_current_config_unchanged() {
+        let current = config(json!({"version": 1, "auto-password-input": {"account-name": "me"}}));
+        let mut migrated = current.clone();
+        assert_eq!(migrate(&mut migrated), Ok(CURRENT_VERSION));
+        assert_eq!(migrated, current);
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let error = migrate(&mut config(json!({"version": 2}))).unwrap_err();
+        assert_eq!(error, MigrationError::NewerVersion(2));
+        assert!(error.to_string().contains("newer than"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_invalid_version() {
+        for version in [json!(-1), json!(1.5), json!("1")] {
+            let error = migrate(&mut config(json!({ "version": version }))).unwrap_err();
+            assert_eq!(error, MigrationError::InvalidVersion);
+        }
+    }
+}
@@ -1,50 +1,364 @@
-use chrono::{self, Duration, Local};
-use std::env;
+use chrono::{self, Duration, Local, NaiveDateTime, NaiveTime};
+use std::{env, fs, path::Path};
+use tracing::{debug, error, info, warn};
 
 pub mod config;
+mod bar;
+mod catch_up;
+mod check;
+mod cooldown;
+mod daemon;
+mod history;
+mod hooks;
+mod layers;
+mod logging;
+mod metrics;
+mod migrations;
+mod notifier;
+mod pause;
 mod plist;
+mod reload;
+mod report;
+mod signing;
+mod skips;
+mod stats;
+mod warning;
+mod webhooks;
 mod begin_block;
 
 mod utils;
 pub use utils::ResultE;
 
 use begin_block::begin_block_until;
+use catch_up::Decision;
+use check::Severity;
+use history::ExecuteOutcome;
 use config::Config;
 use plist::LaunchAgentSchedule;
+use webhooks::EventKind;
 
 const MAIN_AGENT: &str = "com.main-auto-selfcontrol-rs.plist";
 
-pub fn deploy(config: &Config) -> ResultE<()> {
+pub fn deploy(config: &Config, config_path: &Path) -> ResultE<()> {
+    reload::check_deploy(config, config_path)?;
+
     let command = env::current_exe()?;
     let command = command
         .to_str()
         .ok_or_else(|| "invalid path to this binary")?;
 
-    let plist = plist::build_launch_agent_plist(
-        MAIN_AGENT,
-        command,
-        &vec!["--execute"],
-        &LaunchAgentSchedule::Periodic(Duration::seconds(30)),
-        true,
-    );
+    // launchd runs agents from /, so the agent needs an absolute path to the config in use
+    let config_path = fs::canonicalize(config_path)?;
+    let config_path = config_path
+        .to_str()
+        .ok_or_else(|| "invalid path to config file")?;
+    let log_path = logging::log_path()?;
+    let log_path = log_path.to_str().ok_or("invalid path to log file")?;
+
+    let (args, schedule) = match config.daemon {
+        true => (vec!["--daemon", "--config", config_path], LaunchAgentSchedule::KeepAlive),
+        false => (
+            vec!["--execute", "--config", config_path],
+            LaunchAgentSchedule::Periodic(Duration::seconds(30)),
+        ),
+    };
+    // launchd doesn't pass on the environment, so the agent keeps its state where this does
+    let environment = config::xdg_environment();
+    let plist =
+        plist::build_launch_agent_plist(MAIN_AGENT, command, &args, &environment, &schedule, true, log_path);
     config.install_agent(MAIN_AGENT, &plist)?;
+    reload::record_deployed(Path::new(config_path))?;
+    Ok(())
+}
+
+// -v and -q raise and lower the log level, see logging.rs
+pub fn init_logging(verbose: u8, quiet: u8) -> ResultE<()> {
+    logging::init(verbose, quiet)
+}
+
+// the config to --execute with, redeploying if it changed since the last deploy, see reload.rs
+pub fn load_config_for_execute(config_path: &Path) -> ResultE<Config> {
+    reload::load_for_execute(config_path)
+}
+
+pub fn check(config_path: &Path) -> ResultE<()> {
+    let diagnostics = check::check_config(config_path);
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return Err("config check failed".into());
+    }
+    if diagnostics.is_empty() {
+        println!("{}: ok", config_path.display());
+    }
+    Ok(())
+}
+
+pub fn print_pending_changes(config_path: &Path) -> ResultE<()> {
+    let pending = cooldown::read_pending(&fs::canonicalize(config_path)?)?;
+    if pending.is_empty() {
+        println!("No pending changes");
+    }
+    for change in pending {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+pub fn cancel_pending_change(config_path: &Path, id: u32) -> ResultE<()> {
+    cooldown::cancel_pending(&fs::canonicalize(config_path)?, id)
+}
+
+// pauses are checked against the config in force, so lowering pause-notice-hours in the config
+// file doesn't help until it has been deployed (and waited out any cooldown)
+pub fn pause(config_path: &Path, from: &str, to: &str) -> ResultE<()> {
+    let config = reload::load_in_force(config_path)?;
+    let (from, to) = (pause::parse_date(from, false)?, pause::parse_date(to, true)?);
+    pause::request_pause(&config, from, to, Local::now().naive_local())?;
+    println!(
+        "Blocks are paused from {} to {}",
+        from.format("%Y-%m-%d %H:%M"),
+        to.format("%Y-%m-%d %H:%M")
+    );
+    Ok(())
+}
+
+// the --from and --to dates, to defaulting to now
+fn parse_date_range(
+    from: Option<&str>,
+    to: Option<&str>,
+    default_from: NaiveDateTime,
+    now: NaiveDateTime,
+) -> ResultE<(NaiveDateTime, NaiveDateTime)> {
+    let from = match from {
+        Some(from) => pause::parse_date(from, false)?,
+        None => default_from,
+    };
+    let to = match to {
+        Some(to) => pause::parse_date(to, true)?,
+        None => now,
+    };
+    if to <= from {
+        return Err("--to must be after --from".into());
+    }
+    Ok((from, to))
+}
+
+// prints the history from from (default a week ago) until to (default now), as json lines
+pub fn print_history(from: Option<&str>, to: Option<&str>) -> ResultE<()> {
+    let now = Local::now().naive_local();
+    let (from, to) = parse_date_range(from, to, now - Duration::days(7), now)?;
+    for record in history::query(from, to)? {
+        println!("{}", serde_json::to_string(&record)?);
+    }
+    Ok(())
+}
+
+// prints stats from from (default the start of the day a week ago) until to (default now), as a
+// table or json
+pub fn print_stats(config_path: &Path, from: Option<&str>, to: Option<&str>, json: bool) -> ResultE<()> {
+    let config = reload::load_in_force(config_path)?;
+    let now = Local::now().naive_local();
+    let week_ago = (now.date() - Duration::days(6)).and_time(NaiveTime::MIN);
+    let (from, to) = parse_date_range(from, to, week_ago, now)?;
+    let stats = stats::compute(&config, from, to, now)?;
+    match json {
+        true => println!("{}", serde_json::to_string_pretty(&stats)?),
+        false => println!("{}", stats),
+    }
+    Ok(())
+}
+
+// writes the report on the week containing week_of (default today) to output (default
+// report-<week>.<extension> in the current directory), see report.rs
+pub fn write_report(
+    config_path: &Path,
+    format: &str,
+    week_of: Option<&str>,
+    output: Option<&Path>,
+) -> ResultE<()> {
+    let config = reload::load_in_force(config_path)?;
+    let now = Local::now().naive_local();
+    let format = report::Format::from_name(format)?;
+    let week_of = match week_of {
+        Some(week_of) => pause::parse_date(week_of, false)?.date(),
+        None => now.date(),
+    };
+    let report = report::build(&config, week_of, now)?;
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => Path::new(&report.default_file_name(format)).to_path_buf(),
+    };
+    fs::write(&output, report.render(format))?;
+    println!("Wrote {}", output.display());
     Ok(())
 }
 
+// executes at each block boundary until stopped, see daemon.rs
+pub fn run_daemon(config_path: &Path) -> ResultE<()> {
+    daemon::run(config_path)
+}
+
+// serves /metrics until stopped, reloading the config in force for each scrape
+pub fn serve_metrics(config_path: &Path) -> ResultE<()> {
+    let config = reload::load_in_force(config_path)?;
+    metrics::serve(&config.metrics, || reload::load_in_force(config_path))
+}
+
+pub fn print_pauses() -> ResultE<()> {
+    let pauses = pause::upcoming_pauses(Local::now().naive_local())?;
+    if pauses.is_empty() {
+        println!("No pauses");
+    }
+    for pause in pauses {
+        println!("{}", pause);
+    }
+    Ok(())
+}
+
+pub fn cancel_pause(id: u32) -> ResultE<()> {
+    pause::cancel_pause(id)
+}
+
+pub fn skip_next(config_path: &Path) -> ResultE<()> {
+    let config = reload::load_in_force(config_path)?;
+    let now = Local::now().naive_local();
+    let (start, end) = skips::skip_next(&config, now)?;
+    let event = webhooks::Event::new(EventKind::Skipped, Some(start), end, None);
+    webhooks::send(&config.webhooks, event);
+    println!(
+        "Skipped the block from {} to {}, {} skips left this week",
+        start.format("%a %Y-%m-%d %H:%M"),
+        end.format("%H:%M"),
+        skips::skips_left(&config, now)?
+    );
+    Ok(())
+}
+
+pub fn print_status(config_path: &Path) -> ResultE<()> {
+    let config = reload::load_in_force(config_path)?;
+    let now = Local::now().naive_local();
+    let format = "%a %Y-%m-%d %H:%M";
+
+    match config.get_active_block_start(now).zip(config.get_active_block_end(now)) {
+        Some((start, _)) if skips::is_skipped(start)? => println!("Active block: skipped"),
+        Some(_) if pause::is_paused(now)? => println!("Active block: paused"),
+        Some((_, end)) => println!("Active block: until {}", end.format(format)),
+        None => println!("Active block: none"),
+    }
+    match skips::next_block(&config, now)? {
+        Some((start, end)) => println!(
+            "Next block: {} until {}",
+            start.format(format),
+            end.format(format)
+        ),
+        None => println!("Next block: none in the next week"),
+    }
+    for pause in pause::upcoming_pauses(now)? {
+        println!("Pause: {}", pause);
+    }
+    println!(
+        "Skips left this week: {} of {}",
+        skips::skips_left(&config, now)?,
+        config.skips_per_week
+    );
+    let pending = cooldown::read_pending(&fs::canonicalize(config_path)?)?;
+    if !pending.is_empty() {
+        println!("Pending config changes: {}, see --pending_changes", pending.len());
+    }
+    Ok(())
+}
+
+// prints a line of status for a status bar, see bar.rs
+pub fn print_status_bar(config_path: &Path, format: &str) -> ResultE<()> {
+    let config = reload::load_in_force(config_path)?;
+    let format = bar::Format::from_name(format)?;
+    let status = bar::status(&config, Local::now().naive_local())?;
+    println!("{}", status.render(format)?);
+    Ok(())
+}
+
+pub fn generate_signing_key(private_key_path: &Path) -> ResultE<String> {
+    signing::generate_key(private_key_path)
+}
+
+pub fn sign_config(config_path: &Path, private_key_path: &Path) -> ResultE<()> {
+    signing::sign_config(config_path, private_key_path)
+}
+
 pub fn execute_for_duration(config: &Config, duration: Duration) -> ResultE<()> {
     let now = Local::now().naive_local();
-    begin_block_until(config, now + duration)
+    begin_block_until(config, now + duration).map(|_| ())
 }
 
 pub fn execute(config: &Config) -> ResultE<()> {
-    let block = config.get_currently_active_block();
-    let Some((block_start_time, block_end_time)) = block else { return Ok(()) };
+    let now = Local::now().naive_local();
+    // the ended event is queued first so it's sent before the next block's events, but the on-end
+    // hook and the POSTs wait until after the next block is started, so they can't delay it
+    let ended_block = hooks::take_ended_block(now);
+    if let Some(block_end) = ended_block {
+        let event = webhooks::Event::new(EventKind::Ended, None, block_end, None);
+        webhooks::queue(&config.webhooks, event);
+    }
+    // a broken warning mustn't stop the block itself
+    if let Err(e) = warning::warn_before_next_block(config, now) {
+        warn!("failed to warn before the next block: {}", e);
+    }
+
+    let block_start = config.get_active_block_start(now);
+    let block_end = config.get_active_block_end(now);
+    let outcome = execute_block(config, now, block_start, block_end);
+    match &outcome {
+        Ok(ExecuteOutcome::NoBlock) => debug!("no active block"),
+        Ok(outcome) => info!(?outcome, ?block_start, ?block_end, "executed"),
+        Err(e) => error!(?block_start, ?block_end, "execute failed: {}", e),
+    }
+    if let Some(block_end) = ended_block {
+        hooks::run(&config.hooks, hooks::Event::OnEnd, block_end, None);
+    }
+    webhooks::flush_outbox(&config.webhooks);
+    history::record(history::Entry::Execute {
+        outcome: *outcome.as_ref().unwrap_or(&ExecuteOutcome::Failed),
+        block_start,
+        block_end,
+        runs: None,
+        last_run: None,
+    });
+    metrics::write_textfile(config, Local::now().naive_local());
+    outcome.map(|_| ())
+}
 
-    let mut block_end = Local::now().naive_local().date().and_time(block_end_time);
-    if block_start_time > block_end_time {
-        block_end += Duration::days(1);
+fn execute_block(
+    config: &Config,
+    now: NaiveDateTime,
+    block_start: Option<NaiveDateTime>,
+    block_end: Option<NaiveDateTime>,
+) -> ResultE<ExecuteOutcome> {
+    let Some(block_end) = block_end else { return Ok(ExecuteOutcome::NoBlock) };
+    if block_start.map_or(Ok(false), skips::is_skipped)? {
+        info!("this block was skipped with --skip_next");
+        return Ok(ExecuteOutcome::Skipped);
     }
+    if pause::is_paused(now)? {
+        info!("blocks are paused, see --pauses");
+        return Ok(ExecuteOutcome::Paused);
+    }
+    let block_end = match block_start {
+        Some(block_start) => match catch_up::decide(config, block_start, block_end, now)? {
+            Decision::StartUntil { block_end } => block_end,
+            Decision::Skip => {
+                info!("this block's start was missed, and catch-up.policy is skip");
+                return Ok(ExecuteOutcome::MissedSkipped);
+            }
+        },
+        None => block_end,
+    };
 
-    begin_block_until(config, block_end)
+    match begin_block_until(config, block_end)? {
+        true => Ok(ExecuteOutcome::Started),
+        false => Ok(ExecuteOutcome::AlreadyActive),
+    }
 }
 
@@ -0,0 +1,230 @@
+// Desktop notifications, when blocks start or fail to, and before they start (see warning.rs).
+// The backend is chosen with notifications.backend:
+//   osascript (default): the macOS notification center
+//   notify-send: a freedesktop.org notification over D-Bus, sent with notify-send
+//   command: notifications.command, run with sh -c and the notification in
+//            $ASCRS_NOTIFICATION_TITLE and $ASCRS_NOTIFICATION_BODY
+//   none: no notifications
+// Failing to notify is only ever printed, it never stops a block.
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::process::{Command, Output};
+use tracing::warn;
+
+use super::ResultE;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Notifications {
+    backend: Backend,
+    // for the command backend
+    command: Option<String>,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Osascript,
+            command: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum Backend {
+    Osascript,
+    NotifySend,
+    Command,
+    None,
+}
+
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+impl Notification {
+    pub fn new(title: &str, body: impl Into<String>) -> Self {
+        Self {
+            title: title.to_string(),
+            body: body.into(),
+        }
+    }
+}
+
+pub trait Notifier {
+    fn notify(&self, notification: &Notification) -> ResultE<()>;
+}
+
+struct Osascript;
+
+impl Notifier for Osascript {
+    fn notify(&self, notification: &Notification) -> ResultE<()> {
+        // passed as arguments rather than spliced into the script, so nothing needs escaping
+        let output = Command::new("osascript")
+            .args(["-e", "on run argv"])
+            .args(["-e", "display notification (item 2 of argv) with title (item 1 of argv)"])
+            .args(["-e", "end run"])
+            .arg(&notification.title)
+            .arg(&notification.body)
+            .output()?;
+        check_output("osascript", output)
+    }
+}
+
+struct NotifySend;
+
+impl Notifier for NotifySend {
+    fn notify(&self, notification: &Notification) -> ResultE<()> {
+        let output = Command::new("notify-send")
+            .arg("--app-name=auto-selfcontrol-rs")
+            .arg(&notification.title)
+            .arg(&notification.body)
+            .output()?;
+        check_output("notify-send", output)
+    }
+}
+
+struct ShellCommand(String);
+
+impl Notifier for ShellCommand {
+    fn notify(&self, notification: &Notification) -> ResultE<()> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&self.0)
+            .env("ASCRS_NOTIFICATION_TITLE", &notification.title)
+            .env("ASCRS_NOTIFICATION_BODY", &notification.body)
+            .output()?;
+        check_output("notifications.command", output)
+    }
+}
+
+struct Disabled;
+
+impl Notifier for Disabled {
+    fn notify(&self, _notification: &Notification) -> ResultE<()> {
+        Ok(())
+    }
+}
+
+pub fn from_config(notifications: &Notifications) -> ResultE<Box<dyn Notifier>> {
+    Ok(match notifications.backend {
+        Backend::Osascript => Box::new(Osascript),
+        Backend::NotifySend => Box::new(NotifySend),
+        Backend::Command => Box::new(ShellCommand(
+            notifications
+                .command
+                .clone()
+                .ok_or("notifications: the command backend needs notifications.command")?,
+        )),
+        Backend::None => Box::new(Disabled),
+    })
+}
+
+// the notifier from the config, or one that doesn't notify if the config is missing a setting
+pub fn from_config_or_disabled(notifications: &Notifications) -> Box<dyn Notifier> {
+    from_config(notifications).unwrap_or_else(|e| {
+        warn!("failed to send notification: {}", e);
+        Box::new(Disabled)
+    })
+}
+
+// notifies with the backend from the config, logging rather than returning any error
+pub fn notify(notifications: &Notifications, notification: &Notification) {
+    notify_with(from_config_or_disabled(notifications).as_ref(), notification);
+}
+
+// notifies with notifier, logging rather than returning any error
+pub fn notify_with(notifier: &dyn Notifier, notification: &Notification) {
+    if let Err(e) = notifier.notify(notification) {
+        warn!("failed to send notification: {}", e);
+    }
+}
+
+// keeps each notification instead of showing it, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct Recording(pub std::cell::RefCell<Vec<Notification>>);
+
+#[cfg(test)]
+impl Notifier for Recording {
+    fn notify(&self, notification: &Notification) -> ResultE<()> {
+        self.0.borrow_mut().push(Notification::new(&notification.title, notification.body.clone()));
+        Ok(())
+    }
+}
+
+fn check_output(name: &str, output: Output) -> ResultE<()> {
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(format!(
+        "{} failed ({}): {}",
+        name,
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn notifications(yaml: &str) -> Notifications {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn command_backend_runs_command_with_notification() {
+        let output = env::temp_dir().join(format!("ascrs-notifier-{}", std::process::id()));
+        let notifications = notifications(&format!(
+            "backend: command\ncommand: printf '%s|%s' \"$ASCRS_NOTIFICATION_TITLE\" \"$ASCRS_NOTIFICATION_BODY\" > '{}'",
+            output.display()
+        ));
+        let notifier = from_config(&notifications).unwrap();
+        notifier.notify(&Notification::new("Block started", "Blocking until 14:00")).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "Block started|Blocking until 14:00");
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn command_backend_needs_command() {
+        let error = from_config(&notifications("backend: command")).err().unwrap();
+        assert!(error.to_string().contains("needs notifications.command"));
+    }
+
+    #[test]
+    fn none_backend_notifies_nothing() {
+        let notifier = from_config(&notifications("backend: none")).unwrap();
+        assert!(notifier.notify(&Notification::new("Block started", "")).is_ok());
+    }
+
+    #[test]
+    fn unknown_backend_is_rejected() {
+        assert!(serde_yaml::from_str::<Notifications>("backend: file").is_err());
+    }
+
+    #[test]
+    fn notify_delivers_in_order_through_the_configured_backend() {
+        let output = env::temp_dir().join(format!("ascrs-notifier-order-{}", std::process::id()));
+        let notifications = notifications(&format!(
+            "backend: command\ncommand: echo \"$ASCRS_NOTIFICATION_TITLE\" >> '{}'",
+            output.display()
+        ));
+        notify(&notifications, &Notification::new("first", "1"));
+        notify(&notifications, &Notification::new("second", "2"));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "first\nsecond\n");
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn misconfigured_backend_falls_back_to_notifying_nothing() {
+        let notifications = notifications("backend: command");
+        let notifier = from_config_or_disabled(&notifications);
+        assert!(notifier.notify(&Notification::new("Block started", "")).is_ok());
+        notify(&notifications, &Notification::new("Block started", ""));
+    }
+}
@@ -0,0 +1,142 @@
+// Vacation / pause mode. --pause suppresses every scheduled block between two dates. A pause must
+// be requested at least pause-notice-hours before it starts, so it can't be used on impulse to get
+// out of today's blocks. Pauses are kept in pauses.json in the state dir, which --execute consults.
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::PathBuf,
+};
+
+use super::{
+    config::{self, Config},
+    ResultE,
+};
+
+const PAUSES_FILE: &str = "pauses.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct Pause {
+    id: u32,
+    from: NaiveDateTime,
+    pub(super) to: NaiveDateTime,
+    requested_at: NaiveDateTime,
+}
+
+impl Pause {
+    pub fn covers(&self, time: NaiveDateTime) -> bool {
+        self.from <= time && time < self.to
+    }
+}
+
+impl Display for Pause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>3}  {} -> {}",
+            self.id,
+            self.from.format("%Y-%m-%d %H:%M"),
+            self.to.format("%Y-%m-%d %H:%M")
+        )
+    }
+}
+
+// accepts "YYYY-MM-DD HH:MM", or a plain date meaning the start of that day- or for the end of a
+// pause, the end of that day
+pub fn parse_date(date: &str, is_end: bool) -> ResultE<NaiveDateTime> {
+    if let Ok(date_time) = NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M") {
+        return Ok(date_time);
+    }
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("{}: expected YYYY-MM-DD or \"YYYY-MM-DD HH:MM\"", date))?;
+    let start = day.and_time(NaiveTime::MIN);
+    Ok(match is_end {
+        true => start + Duration::days(1),
+        false => start,
+    })
+}
+
+fn pauses_path() -> ResultE<PathBuf> {
+    Ok(config::state_dir()?.join(PAUSES_FILE))
+}
+
+fn read_pauses() -> ResultE<Vec<Pause>> {
+    let path = pauses_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn write_pauses(pauses: &Vec<Pause>) -> ResultE<()> {
+    let path = pauses_path()?;
+    if pauses.is_empty() {
+        return match path.exists() {
+            true => fs::remove_file(path).map_err(|e| e.into()),
+            false => Ok(()),
+        };
+    }
+    fs::create_dir_all(config::state_dir()?)?;
+    fs::write(path, serde_json::to_string_pretty(pauses)?)?;
+    Ok(())
+}
+
+pub fn request_pause(
+    config: &Config,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    now: NaiveDateTime,
+) -> ResultE<()> {
+    if to <= from {
+        return Err("the pause must end after it starts".into());
+    }
+    let earliest_start = now + Duration::hours(config.pause_notice_hours as i64);
+    if from < earliest_start {
+        return Err(format!(
+            "pauses must be requested at least {} hours in advance (pause-notice-hours), the \
+            earliest this pause can start is {}",
+            config.pause_notice_hours,
+            earliest_start.format("%Y-%m-%d %H:%M")
+        )
+        .into());
+    }
+
+    let mut pauses = read_pauses()?;
+    pauses.retain(|pause| pause.to > now);
+    let id = pauses.iter().map(|pause| pause.id).max().unwrap_or(0) + 1;
+    pauses.push(Pause {
+        id,
+        from,
+        to,
+        requested_at: now,
+    });
+    write_pauses(&pauses)
+}
+
+// cancelling only ever adds blocked time back, so it's allowed at any time
+pub fn cancel_pause(id: u32) -> ResultE<()> {
+    let mut pauses = read_pauses()?;
+    let pause_count = pauses.len();
+    pauses.retain(|pause| pause.id != id);
+    if pauses.len() == pause_count {
+        return Err(format!("no pause with id {}", id).into());
+    }
+    write_pauses(&pauses)
+}
+
+// pauses which haven't ended yet
+pub fn upcoming_pauses(now: NaiveDateTime) -> ResultE<Vec<Pause>> {
+    let mut pauses = read_pauses()?;
+    pauses.retain(|pause| pause.to > now);
+    Ok(pauses)
+}
+
+pub fn is_paused(now: NaiveDateTime) -> ResultE<bool> {
+    Ok(read_pauses()?.iter().any(|pause| pause.covers(now)))
+}
+
+// every pause, ended or not, to check many times with one read of pauses.json
+pub fn pauses() -> ResultE<Vec<Pause>> {
+    read_pauses()
+}
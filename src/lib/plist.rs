@@ -4,15 +4,20 @@ pub fn build_launch_agent_plist(
     name: &str,
     command: &str,
     args: &Vec<&str>,
+    environment: &[(&str, String)],
     schedule: &LaunchAgentSchedule,
     run_at_load: bool,
+    log_path: &str,
 ) -> String {
-    let parts = vec![
-        build_plist_header(name),
-        build_plist_commands(command, args),
+    let mut parts = vec![build_plist_header(name), build_plist_commands(command, args)];
+    if !environment.is_empty() {
+        parts.push(build_plist_environment(environment));
+    }
+    parts.extend([
         build_plist_schedule(schedule, run_at_load),
+        build_plist_log(log_path),
         build_plist_footer(),
-    ];
+    ]);
     parts.join("\n")
 }
 
@@ -45,10 +50,28 @@ fn build_plist_commands(command: &str, args: &Vec<&str>) -> String {
     )
 }
 
+fn build_plist_environment(environment: &[(&str, String)]) -> String {
+    let variables = environment
+        .iter()
+        .map(|(name, value)| format!("        <key>{}</key>\n        <string>{}</string>", name, value))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        r#"    <key>EnvironmentVariables</key>
+    <dict>
+{}
+    </dict>"#,
+        variables
+    )
+}
+
 #[allow(dead_code)]
 pub enum LaunchAgentSchedule<'a> {
     Calendar(&'a Vec<NaiveTime>),
     Periodic(Duration),
+    // restarted by launchd whenever it exits, for --daemon
+    KeepAlive,
 }
 fn build_plist_schedule(schedule: &LaunchAgentSchedule, run_at_load: bool) -> String {
     let timings = match schedule {
@@ -60,6 +83,10 @@ fn build_plist_schedule(schedule: &LaunchAgentSchedule, run_at_load: bool) -> St
             )
         }
 
+        LaunchAgentSchedule::KeepAlive => r#"    <key>KeepAlive</key>
+    <true/>"#
+            .to_string(),
+
         LaunchAgentSchedule::Calendar(start_times) => {
             let start_times = start_times
                 .iter()
@@ -100,6 +127,17 @@ fn build_plist_schedule(schedule: &LaunchAgentSchedule, run_at_load: bool) -> St
     }
 }
 
+// launchd otherwise discards the agent's output, so anything printed and panics go to the log too
+fn build_plist_log(log_path: &str) -> String {
+    format!(
+        r#"    <key>StandardOutPath</key>
+    <string>{0}</string>
+    <key>StandardErrorPath</key>
+    <string>{0}</string>"#,
+        log_path
+    )
+}
+
 fn build_plist_footer() -> String {
     r#"</dict>
 </plist>"#
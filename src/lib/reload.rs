@@ -0,0 +1,190 @@
+// Hot reloading of the config. --deploy records a hash and snapshot of the config it deployed, and
+// each --execute compares the config on disk against it. A valid changed config is redeployed, an
+// invalid one is warned about, and the last known good snapshot stays in force until it's fixed.
+// The same goes for changes refused by strict mode, until the active block ends. Changes under a
+// cooldown are queued here too, see cooldown.rs, and changes missing an accountability partner's
+// signature are refused, see signing.rs. Only a config newer than this build supports is an error
+// rather than falling back, see migrations.rs.
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tracing::{info, warn};
+
+use super::{
+    config::{self, Config, ConfigError},
+    cooldown::{self, PendingChange},
+    layers, signing, ResultE,
+};
+
+const DEPLOYED_FILE: &str = "deployed.json";
+
+#[derive(Serialize, Deserialize)]
+struct Deployed {
+    config_path: PathBuf,
+    hash: u64,
+    // the merged config, before environment variable overrides
+    config: Value,
+    // the changes pending when it was deployed, held back until they're due even without
+    // pending-changes.json
+    #[serde(default)]
+    pending: Vec<PendingChange>,
+}
+
+pub fn record_deployed(config_path: &Path) -> ResultE<()> {
+    let config_path = fs::canonicalize(config_path)?;
+    let config = layers::load(&config_path)?.value;
+    let deployed = Deployed {
+        hash: hash(&config),
+        pending: cooldown::read_pending(&config_path)?,
+        config_path,
+        config,
+    };
+
+    let state_dir = config::state_dir()?;
+    fs::create_dir_all(&state_dir)?;
+    fs::write(
+        state_dir.join(DEPLOYED_FILE),
+        serde_json::to_string_pretty(&deployed)?,
+    )?;
+    Ok(())
+}
+
+// the config --execute should use, redeploying first if it changed since it was deployed
+pub fn load_for_execute(config_path: &Path) -> ResultE<Config> {
+    let now = Local::now().naive_local();
+    let Some(deployed) = read_deployed()? else {
+        let config = Config::build(config_path)?;
+        check_not_deployed(&config)?;
+        return Ok(config);
+    };
+    // the config may have been deleted, in which case the deployed one stays in force
+    let config_path = fs::canonicalize(config_path).unwrap_or(config_path.to_path_buf());
+    if config_path != deployed.config_path {
+        // eg a manual --execute with another config, which was never deployed
+        return Config::build(&config_path);
+    }
+
+    let changed = match layers::load(&config_path) {
+        Ok(merged) => hash(&merged.value) != deployed.hash,
+        Err(_) => true,
+    };
+    if !changed {
+        let mut config = Config::build(&config_path)?;
+        cooldown::hold_back(&mut config, &deployed.pending, now);
+        cooldown::apply_pending(&mut config, &config_path, now)?;
+        return Ok(config);
+    }
+
+    let in_force = in_force_config(deployed, now)?;
+    let new_config = Config::build(&config_path).and_then(|new_config| {
+        in_force.check_not_weakened_by(&new_config, now)?;
+        signing::check_signed_if_weakened(&config_path, &in_force, &new_config)?;
+        Ok(new_config)
+    });
+    match new_config {
+        Ok(mut new_config) => {
+            cooldown::queue_changes(&config_path, &in_force, &new_config, now)?;
+            redeploy(&config_path)?;
+            cooldown::apply_pending(&mut new_config, &config_path, now)?;
+            Ok(new_config)
+        }
+        // refused rather than fallen back from, as the config may rely on what this build lacks
+        Err(e) if e.downcast_ref::<ConfigError>().is_some_and(|e| e.newer_version) => Err(e),
+        Err(e) => {
+            warn!(
+                "{} has changed since it was deployed, but can't be used- the last \
+                deployed config stays in force:\n{}",
+                config_path.display(),
+                e
+            );
+            Ok(in_force)
+        }
+    }
+}
+
+// checks a config about to be deployed against the one in force: refusing it if strict mode is
+// on and it would weaken the active block, or if it weakens the config without the partner's
+// signature, and queueing changes which are under a cooldown
+pub fn check_deploy(config: &Config, config_path: &Path) -> ResultE<()> {
+    let now = Local::now().naive_local();
+    let Some(deployed) = read_deployed()? else { return check_not_deployed(config) };
+    let in_force = in_force_config(deployed, now)?;
+
+    in_force.check_not_weakened_by(config, now)?;
+    signing::check_signed_if_weakened(config_path, &in_force, config)?;
+    cooldown::queue_changes(&fs::canonicalize(config_path)?, &in_force, config, now)
+}
+
+// the config in force for config_path, without redeploying it: the deployed config if
+// config_path was deployed, else config_path itself
+pub fn load_in_force(config_path: &Path) -> ResultE<Config> {
+    let now = Local::now().naive_local();
+    let config_path = fs::canonicalize(config_path).unwrap_or(config_path.to_path_buf());
+    match read_deployed()? {
+        Some(deployed) if deployed.config_path == config_path => in_force_config(deployed, now),
+        _ => Config::build(&config_path),
+    }
+}
+
+// the deployed config, with the schedule from before any pending changes still in force
+fn in_force_config(deployed: Deployed, now: NaiveDateTime) -> ResultE<Config> {
+    let mut config = Config::from_snapshot(deployed.config)?;
+    cooldown::hold_back(&mut config, &deployed.pending, now);
+    cooldown::apply_pending(&mut config, &deployed.config_path, now)?;
+    Ok(config)
+}
+
+// without a snapshot any config goes, so one going missing (eg deleted, or deploying with another
+// XDG_STATE_HOME) while the agent is installed is refused rather than taken as a first deploy
+fn check_not_deployed(config: &Config) -> ResultE<()> {
+    if !config.is_agent_installed(super::MAIN_AGENT) {
+        return Ok(());
+    }
+    Err(format!(
+        "the launch agent {} is installed, but {} is missing, so the config in force is unknown- \
+        remove the agent's plist to deploy afresh",
+        super::MAIN_AGENT,
+        config::state_dir()?.join(DEPLOYED_FILE).display()
+    )
+    .into())
+}
+
+fn read_deployed() -> ResultE<Option<Deployed>> {
+    let path = config::state_dir()?.join(DEPLOYED_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+}
+
+fn redeploy(config_path: &Path) -> ResultE<()> {
+    info!("{} has changed, redeploying", config_path.display());
+    record_deployed(config_path)?;
+
+    // reinstalling the agent makes launchd kill the agent's processes, which includes this one if
+    // it was started by the agent- so deploy from a process in its own process group, which
+    // survives it
+    Command::new(env::current_exe()?)
+        .arg("--deploy")
+        .arg("--config")
+        .arg(config_path)
+        .process_group(0)
+        .spawn()?;
+    Ok(())
+}
+
+// DefaultHasher isn't guaranteed stable between rust versions, at worst an upgraded binary
+// redeploys an unchanged config once
+fn hash(config: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    config.to_string().hash(&mut hasher);
+    hasher.finish()
+}
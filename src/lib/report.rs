@@ -0,0 +1,345 @@
+// Weekly focus reports, eg for a team retro. --report <FORMAT> --week [DATE] reports on the week
+// (Monday to Sunday) containing DATE, this week by default: each block scheduled so far, how much
+// of it was enforced, which blocks were missed, and why: a missed start (see catch_up.rs) and the
+// SelfControl errors during them (see history.rs). Rendered as Markdown, as a self-contained HTML
+// page (no external styles or scripts), or as CSV with a row per block, and written to --output,
+// by default report-<week>.<md|html|csv>.
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use std::collections::BTreeMap;
+
+use super::{
+    config::{self, Config},
+    history::{self, Entry, ExecuteOutcome, Record, SelfControlErrorKind},
+    skips,
+    stats::{self, Interval, Period, Timeline},
+    ResultE,
+};
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Markdown,
+    Html,
+    Csv,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> ResultE<Self> {
+        match name {
+            "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown report format: {}, expected markdown, html or csv", name).into()),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Html => "html",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Kept,
+    PartlyMissed,
+    Missed,
+    InProgress,
+    Skipped,
+    Paused,
+}
+
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Kept => "kept",
+            Status::PartlyMissed => "partly missed",
+            Status::Missed => "missed",
+            Status::InProgress => "in progress",
+            Status::Skipped => "skipped",
+            Status::Paused => "paused",
+        }
+    }
+}
+
+struct BlockReport {
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    scheduled_minutes: i64,
+    enforced_minutes: i64,
+    missed_minutes: i64,
+    status: Status,
+    // how late the block started, if its start was missed
+    missed_start_minutes: Option<i64>,
+    errors: BTreeMap<SelfControlErrorKind, u32>,
+}
+
+impl BlockReport {
+    fn is_missed(&self) -> bool {
+        matches!(self.status, Status::Missed | Status::PartlyMissed)
+    }
+
+    fn reasons(&self) -> String {
+        let missed_start = self
+            .missed_start_minutes
+            .map(|minutes| format!("start missed by {}m", minutes));
+        let errors = self.errors.iter().map(|(kind, count)| match count {
+            1 => kind.to_string(),
+            _ => format!("{} x{}", kind, count),
+        });
+        missed_start.into_iter().chain(errors).collect::<Vec<_>>().join(", ")
+    }
+}
+
+pub struct Report {
+    person: String,
+    week_start: NaiveDate,
+    summary: Period,
+    blocks: Vec<BlockReport>,
+}
+
+pub fn build(config: &Config, week_of: NaiveDate, now: NaiveDateTime) -> ResultE<Report> {
+    let week_start = skips::week_start(week_of.and_time(NaiveTime::MIN));
+    let week_end = (week_start + Duration::weeks(1)).min(now);
+    if week_start >= now {
+        return Err(format!("the week of {} hasn't started yet", week_start.format("%Y-%m-%d")).into());
+    }
+    // blocks starting on Sunday may run into the next week
+    let to = (week_end + Duration::days(1)).min(now);
+    let records = history::query(week_start - Duration::days(1), to)?;
+    let timeline = Timeline::build(config, &records, week_start, to)?;
+
+    let mut blocks = vec![];
+    for (start, end) in config.get_blocks_between(week_start, week_end) {
+        // a block overnight into Monday belongs to the week before
+        if start < week_start {
+            continue;
+        }
+        blocks.push(block_report(&timeline, &records, (start, end), now)?);
+    }
+
+    Ok(Report {
+        person: config::get_account_name(),
+        week_start: week_start.date(),
+        summary: timeline.period(week_start.date(), (week_start, week_end)),
+        blocks,
+    })
+}
+
+fn block_report(
+    timeline: &Timeline,
+    records: &[Record],
+    (start, end): Interval,
+    now: NaiveDateTime,
+) -> ResultE<BlockReport> {
+    let window = (start, end.min(now));
+    let scheduled = (window.1 - window.0).num_seconds();
+    let enforced = stats::seconds(&timeline.blocked, window);
+
+    let outcome = records.iter().find_map(|record| match record.entry {
+        Entry::Execute {
+            outcome: outcome @ (ExecuteOutcome::Skipped | ExecuteOutcome::Paused),
+            block_start: Some(block_start),
+            ..
+        } if block_start == start => Some(outcome),
+        _ => None,
+    });
+    let status = if outcome == Some(ExecuteOutcome::Skipped) || timeline.is_skipped(start) {
+        Status::Skipped
+    } else if outcome == Some(ExecuteOutcome::Paused) || timeline.is_paused(start) {
+        Status::Paused
+    } else if end > now {
+        Status::InProgress
+    } else if enforced as f64 >= scheduled as f64 * stats::KEPT_DAY_ADHERENCE {
+        Status::Kept
+    } else if enforced == 0 {
+        Status::Missed
+    } else {
+        Status::PartlyMissed
+    };
+
+    let mut missed_start_minutes = None;
+    let mut errors = BTreeMap::new();
+    for record in records {
+        match record.entry {
+            Entry::MissedBlock {
+                block_start,
+                missed_minutes,
+                ..
+            } if block_start == start => missed_start_minutes = Some(missed_minutes),
+            Entry::SelfcontrolError { error, .. } if record.time >= start && record.time < end => {
+                *errors.entry(error).or_insert(0) += 1;
+            }
+            _ => (),
+        }
+    }
+
+    Ok(BlockReport {
+        start,
+        end,
+        scheduled_minutes: scheduled / 60,
+        enforced_minutes: enforced / 60,
+        missed_minutes: match status {
+            Status::Skipped | Status::Paused => 0,
+            _ => (scheduled - enforced) / 60,
+        },
+        status,
+        missed_start_minutes,
+        errors,
+    })
+}
+
+impl Report {
+    pub fn default_file_name(&self, format: Format) -> String {
+        format!("report-{}.{}", self.week_start.format("%Y-%m-%d"), format.extension())
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Markdown => self.render_markdown(),
+            Format::Html => self.render_html(),
+            Format::Csv => self.render_csv(),
+        }
+    }
+
+    fn title(&self) -> String {
+        format!(
+            "Focus report: {}, week of {}",
+            self.person,
+            self.week_start.format("%Y-%m-%d")
+        )
+    }
+
+    // (label, value) lines summarising the week
+    fn summary_lines(&self) -> Vec<(&'static str, String)> {
+        let adherence = self
+            .summary
+            .adherence
+            .map_or("-".to_string(), |adherence| format!("{:.0}%", adherence * 100.0));
+        vec![
+            ("Scheduled", stats::hours(self.summary.scheduled_minutes)),
+            ("Enforced", stats::hours(self.summary.enforced_minutes)),
+            ("Adherence", adherence),
+            ("Blocked in total", stats::hours(self.summary.blocked_minutes)),
+            (
+                "Missed blocks",
+                format!(
+                    "{} of {}",
+                    self.blocks.iter().filter(|block| block.is_missed()).count(),
+                    self.blocks.len()
+                ),
+            ),
+            ("Helper cancels", self.summary.helper_cancels.to_string()),
+        ]
+    }
+
+    // the cells of each block's row, after the header
+    fn rows(&self) -> Vec<[String; 6]> {
+        self.blocks
+            .iter()
+            .map(|block| {
+                [
+                    block.start.format("%a %Y-%m-%d").to_string(),
+                    format!("{}-{}", block.start.format("%H:%M"), block.end.format("%H:%M")),
+                    stats::hours(block.enforced_minutes),
+                    stats::hours(block.missed_minutes),
+                    block.status.name().to_string(),
+                    block.reasons(),
+                ]
+            })
+            .collect()
+    }
+
+    const HEADER: [&str; 6] = ["Day", "Block", "Enforced", "Missed", "Status", "Reasons"];
+
+    fn render_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\n", self.title());
+        for (label, value) in self.summary_lines() {
+            markdown += &format!("- {}: {}\n", label, value);
+        }
+        markdown += "\n## Blocks\n\n";
+        if self.blocks.is_empty() {
+            return markdown + "No blocks were scheduled.\n";
+        }
+        markdown += &format!("| {} |\n", Self::HEADER.join(" | "));
+        markdown += &format!("|{}\n", "---|".repeat(Self::HEADER.len()));
+        for row in self.rows() {
+            markdown += &format!("| {} |\n", row.join(" | "));
+        }
+        markdown
+    }
+
+    fn render_html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+            <style>\n\
+            body {{ font-family: -apple-system, sans-serif; margin: 2em; color: #222; }}\n\
+            table {{ border-collapse: collapse; }}\n\
+            th, td {{ border: 1px solid #ccc; padding: 4px 10px; text-align: left; }}\n\
+            th {{ background: #f3f3f3; }}\n\
+            .missed, .partly-missed {{ color: #b00020; }}\n\
+            .kept {{ color: #1b7f3b; }}\n\
+            </style>\n</head>\n<body>\n<h1>{title}</h1>\n<ul>\n",
+            title = escape_html(&self.title())
+        );
+        for (label, value) in self.summary_lines() {
+            html += &format!("<li>{}: {}</li>\n", label, escape_html(&value));
+        }
+        html += "</ul>\n<h2>Blocks</h2>\n";
+        if self.blocks.is_empty() {
+            return html + "<p>No blocks were scheduled.</p>\n</body>\n</html>\n";
+        }
+        html += "<table>\n<tr>";
+        for cell in Self::HEADER {
+            html += &format!("<th>{}</th>", cell);
+        }
+        html += "</tr>\n";
+        for (block, row) in self.blocks.iter().zip(self.rows()) {
+            html += &format!("<tr class=\"{}\">", block.status.name().replace(' ', "-"));
+            for cell in row {
+                html += &format!("<td>{}</td>", escape_html(&cell));
+            }
+            html += "</tr>\n";
+        }
+        html + "</table>\n</body>\n</html>\n"
+    }
+
+    // a row per block, with plain minutes so it can be summed in a spreadsheet
+    fn render_csv(&self) -> String {
+        let mut csv = "person,date,start,end,scheduled_minutes,enforced_minutes,missed_minutes,\
+            status,reasons\n"
+            .to_string();
+        for block in &self.blocks {
+            let row = [
+                self.person.clone(),
+                block.start.format("%Y-%m-%d").to_string(),
+                block.start.format("%H:%M").to_string(),
+                block.end.format("%H:%M").to_string(),
+                block.scheduled_minutes.to_string(),
+                block.enforced_minutes.to_string(),
+                block.missed_minutes.to_string(),
+                block.status.name().to_string(),
+                block.reasons(),
+            ];
+            csv += &row.map(|field| escape_csv(&field)).join(",");
+            csv += "\n";
+        }
+        csv
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn escape_csv(field: &str) -> String {
+    match field.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
@@ -0,0 +1,94 @@
+// Configs signed by an accountability partner. Once partner-public-key is set in the config in
+// force, a new config that reduces blocked time (see cooldown.rs), turns strict mode off or changes
+// the key is only accepted if it's signed with the partner's private key. The signature covers the
+// merged config with every host's overrides (see layers.rs), and is kept in <config file>.sig next
+// to the config file. Changes
+// that only add blocked time don't need a signature.
+//
+// Keys and signatures are stored base64 encoded. The partner keeps the private key written by
+// --generate_signing_key, and signs each relaxation with --sign_config.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use super::{config::Config, cooldown, layers, ResultE};
+
+// writes a new private key to private_key_path, returning the public key to put in the config
+pub fn generate_key(private_key_path: &Path) -> ResultE<String> {
+    let signing_key = SigningKey::generate(&mut OsRng);
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(private_key_path)
+        .map_err(|e| format!("{}: {}", private_key_path.display(), e))?
+        .write_all(STANDARD.encode(signing_key.to_bytes()).as_bytes())?;
+    Ok(STANDARD.encode(signing_key.verifying_key().to_bytes()))
+}
+
+pub fn sign_config(config_path: &Path, private_key_path: &Path) -> ResultE<()> {
+    let private_key = decode::<32>(&fs::read_to_string(private_key_path)?, "private key")?;
+    let signature = SigningKey::from_bytes(&private_key).sign(&signed_bytes(config_path)?);
+    fs::write(
+        signature_path(config_path),
+        STANDARD.encode(signature.to_bytes()),
+    )?;
+    Ok(())
+}
+
+// refuses new if it weakens in_force, unless it's signed with in_force's partner-public-key
+pub fn check_signed_if_weakened(config_path: &Path, in_force: &Config, new: &Config) -> ResultE<()> {
+    let Some(public_key) = &in_force.partner_public_key else { return Ok(()) };
+    let weakened = cooldown::reduces_blocked_time(in_force, new)
+        || (in_force.strict && !new.strict)
+        || new.partner_public_key != in_force.partner_public_key;
+    if !weakened || is_signed(config_path, public_key)? {
+        return Ok(());
+    }
+    Err(format!(
+        "the new config reduces blocked time, turns strict mode off or changes \
+        partner-public-key, so it must be signed by your accountability partner- {} is missing \
+        or doesn't match the config",
+        signature_path(config_path).display()
+    )
+    .into())
+}
+
+pub fn parse_public_key(public_key: &str) -> ResultE<VerifyingKey> {
+    Ok(VerifyingKey::from_bytes(&decode::<32>(public_key, "partner-public-key")?)?)
+}
+
+fn is_signed(config_path: &Path, public_key: &str) -> ResultE<bool> {
+    let public_key = parse_public_key(public_key)?;
+    let Ok(signature) = fs::read_to_string(signature_path(config_path)) else { return Ok(false) };
+    let Ok(signature) = decode::<64>(&signature, "signature") else { return Ok(false) };
+    Ok(public_key
+        .verify(&signed_bytes(config_path)?, &Signature::from_bytes(&signature))
+        .is_ok())
+}
+
+// the merged config rather than the file itself, so formatting and comments can change freely- with
+// every host's overrides rather than this machine's, so a partner can sign it on their own machine
+fn signed_bytes(config_path: &Path) -> ResultE<Vec<u8>> {
+    Ok(serde_json::to_vec(&layers::load(config_path)?.host_independent)?)
+}
+
+fn signature_path(config_path: &Path) -> PathBuf {
+    let mut file_name = config_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".sig");
+    config_path.with_file_name(file_name)
+}
+
+fn decode<const N: usize>(encoded: &str, what: &str) -> ResultE<[u8; N]> {
+    STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{}: expected {} base64 encoded bytes", what, N).into())
+}
@@ -0,0 +1,101 @@
+// Skip tokens. With skips-per-week set, --skip_next cancels the next scheduled block before it
+// starts, using up one of the week's skips- a block that's already running can't be skipped. Weeks
+// start on Monday. Used skips are kept in skips.json in the state dir, which --execute consults.
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::PathBuf};
+
+use super::{
+    config::{self, Config},
+    pause, ResultE,
+};
+
+const SKIPS_FILE: &str = "skips.json";
+
+#[derive(Serialize, Deserialize)]
+struct Skip {
+    block_start: NaiveDateTime,
+    block_end: NaiveDateTime,
+    used_at: NaiveDateTime,
+}
+
+fn skips_path() -> ResultE<PathBuf> {
+    Ok(config::state_dir()?.join(SKIPS_FILE))
+}
+
+fn read_skips() -> ResultE<Vec<Skip>> {
+    let path = skips_path()?;
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn write_skips(skips: &Vec<Skip>) -> ResultE<()> {
+    fs::create_dir_all(config::state_dir()?)?;
+    fs::write(skips_path()?, serde_json::to_string_pretty(skips)?)?;
+    Ok(())
+}
+
+pub fn week_start(now: NaiveDateTime) -> NaiveDateTime {
+    let monday = now.date() - Duration::days(now.weekday().num_days_from_monday() as i64);
+    monday.and_time(NaiveTime::MIN)
+}
+
+pub fn skips_left(config: &Config, now: NaiveDateTime) -> ResultE<u32> {
+    let week_start = week_start(now);
+    let used = read_skips()?
+        .iter()
+        .filter(|skip| skip.used_at >= week_start)
+        .count() as u32;
+    Ok(config.skips_per_week.saturating_sub(used))
+}
+
+pub fn is_skipped(block_start: NaiveDateTime) -> ResultE<bool> {
+    Ok(read_skips()?
+        .iter()
+        .any(|skip| skip.block_start == block_start))
+}
+
+// the starts of every skipped block, to check many blocks with one read of skips.json
+pub fn skipped_block_starts() -> ResultE<HashSet<NaiveDateTime>> {
+    Ok(read_skips()?.iter().map(|skip| skip.block_start).collect())
+}
+
+// the next block which will actually run: not skipped already, nor paused
+pub fn next_block(
+    config: &Config,
+    now: NaiveDateTime,
+) -> ResultE<Option<(NaiveDateTime, NaiveDateTime)>> {
+    for (start, end) in config.get_upcoming_blocks(now) {
+        if !is_skipped(start)? && !pause::is_paused(start)? {
+            return Ok(Some((start, end)));
+        }
+    }
+    Ok(None)
+}
+
+// skips the next block, returning its (start, end)
+pub fn skip_next(config: &Config, now: NaiveDateTime) -> ResultE<(NaiveDateTime, NaiveDateTime)> {
+    if skips_left(config, now)? == 0 {
+        return Err(format!(
+            "no skips left this week (skips-per-week: {})",
+            config.skips_per_week
+        )
+        .into());
+    }
+    let (block_start, block_end) =
+        next_block(config, now)?.ok_or("there is no upcoming block to skip in the next week")?;
+
+    let mut skips = read_skips()?;
+    // skips from previous weeks of blocks which have ended don't matter anymore
+    let week_start = week_start(now);
+    skips.retain(|skip| skip.used_at >= week_start || skip.block_end > now);
+    skips.push(Skip {
+        block_start,
+        block_end,
+        used_at: now,
+    });
+    write_skips(&skips)?;
+    Ok((block_start, block_end))
+}
@@ -0,0 +1,305 @@
+// Focus statistics from the history (see history.rs), for --stats. Per day and per week:
+//   scheduled: minutes of blocks in the schedule in force, leaving out blocks skipped or paused
+//   blocked: minutes SelfControl was running for, from the block starts in the history
+//   enforced: scheduled minutes which were blocked- adherence is enforced / scheduled
+// A day is kept when at least 95% of its scheduled minutes were enforced, as blocks start up to 30s
+// late while --execute polls. Streaks count kept days in a row over the last year, leaving out a
+// block in progress, days without blocks neither extend nor break them. The schedule is the one in
+// force now, so days before a config change are compared against the new schedule.
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+};
+
+use super::{
+    config::Config,
+    history::{self, Entry, ExecuteOutcome, Record, SelfControlErrorKind},
+    pause::{self, Pause},
+    skips, ResultE,
+};
+
+pub const KEPT_DAY_ADHERENCE: f64 = 0.95;
+const STREAK_WINDOW_DAYS: i64 = 365;
+
+pub type Interval = (NaiveDateTime, NaiveDateTime);
+
+#[derive(Serialize)]
+pub struct Stats {
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    total: Period,
+    days: Vec<Period>,
+    weeks: Vec<Period>,
+    current_streak_days: u32,
+    longest_streak_days: u32,
+}
+
+#[derive(Serialize)]
+pub struct Period {
+    pub start: NaiveDate,
+    pub scheduled_minutes: i64,
+    pub enforced_minutes: i64,
+    pub blocked_minutes: i64,
+    // None without scheduled minutes
+    pub adherence: Option<f64>,
+    pub helper_cancels: u32,
+}
+
+impl Period {
+    fn is_kept(&self) -> Option<bool> {
+        self.adherence.map(|adherence| adherence >= KEPT_DAY_ADHERENCE)
+    }
+}
+
+// what was scheduled and what was blocked over some time, built from the history
+pub struct Timeline {
+    pub scheduled: Vec<Interval>,
+    pub blocked: Vec<Interval>,
+    pub helper_cancels: Vec<NaiveDateTime>,
+    // skips.json and pauses.json, read once rather than for every block
+    skipped: HashSet<NaiveDateTime>,
+    pauses: Vec<Pause>,
+}
+
+impl Timeline {
+    // records must start early enough to include blocks still running at from
+    pub fn build(
+        config: &Config,
+        records: &[Record],
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> ResultE<Self> {
+        let excused = excused_block_starts(records);
+        let skipped = skips::skipped_block_starts()?;
+        let pauses = pause::pauses()?;
+        let scheduled = config
+            .get_blocks_between(from, to)
+            .into_iter()
+            .filter(|(start, _)| {
+                !excused.contains(start)
+                    && !skipped.contains(start)
+                    && !pauses.iter().any(|pause| pause.covers(*start))
+            })
+            .map(|block| clip(block, (from, to)))
+            .collect();
+
+        let blocked = records
+            .iter()
+            .filter_map(|record| match &record.entry {
+                Entry::BlockStarted {
+                    block_end,
+                    selfcontrol_end,
+                } => Some((record.time, selfcontrol_end.unwrap_or(*block_end))),
+                _ => None,
+            })
+            .map(|interval| clip(interval, (from, to)))
+            .collect();
+
+        let helper_cancels = records
+            .iter()
+            .filter(|record| record.time >= from && record.time < to)
+            .filter(|record| {
+                matches!(
+                    record.entry,
+                    Entry::SelfcontrolError {
+                        error: SelfControlErrorKind::UserCancelledHelper,
+                        ..
+                    }
+                )
+            })
+            .map(|record| record.time)
+            .collect();
+
+        Ok(Self {
+            scheduled: merge(scheduled),
+            blocked: merge(blocked),
+            helper_cancels,
+            skipped,
+            pauses,
+        })
+    }
+
+    // whether the block starting at block_start is in skips.json now
+    pub fn is_skipped(&self, block_start: NaiveDateTime) -> bool {
+        self.skipped.contains(&block_start)
+    }
+
+    // whether a pause in pauses.json now covers time
+    pub fn is_paused(&self, time: NaiveDateTime) -> bool {
+        self.pauses.iter().any(|pause| pause.covers(time))
+    }
+
+    pub fn period(&self, start: NaiveDate, window: Interval) -> Period {
+        let scheduled = seconds(&self.scheduled, window);
+        let enforced: i64 = self
+            .scheduled
+            .iter()
+            .flat_map(|scheduled| self.blocked.iter().map(move |blocked| clip(*scheduled, *blocked)))
+            .map(|interval| seconds(&[interval], window))
+            .sum();
+        Period {
+            start,
+            scheduled_minutes: scheduled / 60,
+            enforced_minutes: enforced / 60,
+            blocked_minutes: seconds(&self.blocked, window) / 60,
+            adherence: (scheduled > 0).then(|| enforced as f64 / scheduled as f64),
+            helper_cancels: self
+                .helper_cancels
+                .iter()
+                .filter(|time| **time >= window.0 && **time < window.1)
+                .count() as u32,
+        }
+    }
+
+    // the periods of a day each from from.date() until to, clipped to from..to
+    fn days(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Period> {
+        let mut days = vec![];
+        let mut day = from.date();
+        while day.and_time(NaiveTime::MIN) < to {
+            let day_start = day.and_time(NaiveTime::MIN);
+            let window = clip((day_start, day_start + Duration::days(1)), (from, to));
+            days.push(self.period(day, window));
+            day += Duration::days(1);
+        }
+        days
+    }
+}
+
+// blocks --execute found skipped or paused, which might not be in skips.json or pauses.json anymore
+fn excused_block_starts(records: &[Record]) -> HashSet<NaiveDateTime> {
+    records
+        .iter()
+        .filter_map(|record| match record.entry {
+            Entry::Execute {
+                outcome: ExecuteOutcome::Skipped | ExecuteOutcome::Paused,
+                block_start,
+                ..
+            } => block_start,
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn compute(
+    config: &Config,
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+    now: NaiveDateTime,
+) -> ResultE<Stats> {
+    let to = to.min(now);
+    // SelfControl blocks last at most a day, so a block running at from started at most a day before
+    let records = history::query(from - Duration::days(1), to)?;
+    let timeline = Timeline::build(config, &records, from, to)?;
+
+    let mut weeks = vec![];
+    let mut week_start = skips::week_start(from);
+    while week_start < to {
+        let window = clip((week_start, week_start + Duration::weeks(1)), (from, to));
+        weeks.push(timeline.period(week_start.date(), window));
+        week_start += Duration::weeks(1);
+    }
+
+    let (current_streak_days, longest_streak_days) = streaks(config, now)?;
+    Ok(Stats {
+        from,
+        to,
+        total: timeline.period(from.date(), (from, to)),
+        days: timeline.days(from, to),
+        weeks,
+        current_streak_days,
+        longest_streak_days,
+    })
+}
+
+// (current, longest) streaks of kept days, over the last STREAK_WINDOW_DAYS
+fn streaks(config: &Config, now: NaiveDateTime) -> ResultE<(u32, u32)> {
+    // a block in progress isn't kept or missed yet, as it may have started up to 30s late
+    let to = config.get_active_block_start(now).unwrap_or(now);
+    let records = history::query(to - Duration::days(STREAK_WINDOW_DAYS), to)?;
+    let Some(first) = records.first() else { return Ok((0, 0)) };
+    // blocks before the history began aren't known to have been missed
+    let timeline = Timeline::build(config, &records, first.time, to)?;
+
+    let (mut current, mut longest) = (0, 0);
+    for day in timeline.days(first.time, to) {
+        match day.is_kept() {
+            Some(true) => current += 1,
+            Some(false) => current = 0,
+            None => (),
+        }
+        longest = longest.max(current);
+    }
+    Ok((current, longest))
+}
+
+fn clip((start, end): Interval, (from, to): Interval) -> Interval {
+    (start.max(from), end.min(to))
+}
+
+pub fn seconds(intervals: &[Interval], window: Interval) -> i64 {
+    intervals
+        .iter()
+        .map(|interval| {
+            let (start, end) = clip(*interval, window);
+            (end - start).num_seconds().max(0)
+        })
+        .sum()
+}
+
+// sorted, without empty or overlapping intervals
+fn merge(mut intervals: Vec<Interval>) -> Vec<Interval> {
+    intervals.retain(|(start, end)| start < end);
+    intervals.sort();
+    let mut merged: Vec<Interval> = vec![];
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+pub fn hours(minutes: i64) -> String {
+    format!("{:.1}h", minutes as f64 / 60.0)
+}
+
+fn write_row(f: &mut fmt::Formatter<'_>, label: &str, period: &Period) -> fmt::Result {
+    let adherence = period
+        .adherence
+        .map_or("-".to_string(), |adherence| format!("{:.0}%", adherence * 100.0));
+    writeln!(
+        f,
+        "{:<20}{:>10}{:>10}{:>11}{:>9}{:>16}",
+        label,
+        hours(period.scheduled_minutes),
+        hours(period.enforced_minutes),
+        adherence,
+        hours(period.blocked_minutes),
+        period.helper_cancels
+    )
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<20}{:>10}{:>10}{:>11}{:>9}{:>16}",
+            "", "Scheduled", "Enforced", "Adherence", "Blocked", "Helper cancels"
+        )?;
+        for day in &self.days {
+            write_row(f, &day.start.format("%a %Y-%m-%d").to_string(), day)?;
+        }
+        writeln!(f)?;
+        for week in &self.weeks {
+            write_row(f, &week.start.format("Week of %Y-%m-%d").to_string(), week)?;
+        }
+        writeln!(f)?;
+        write_row(f, "Total", &self.total)?;
+        writeln!(f)?;
+        writeln!(f, "Current streak: {} days", self.current_streak_days)?;
+        write!(f, "Longest streak: {} days", self.longest_streak_days)
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::{Duration, NaiveTime};
+use chrono::{Duration, NaiveTime, Timelike};
 use std::error::Error;
 
 pub fn duration_between(start: NaiveTime, end: NaiveTime) -> Duration {
@@ -9,4 +9,18 @@ pub fn duration_between(start: NaiveTime, end: NaiveTime) -> Duration {
     }
 }
 
+// split a (possibly overnight) block into minute ranges within a single day, a zero-length block
+// being a 24 hour one
+pub fn minute_ranges((start, end): (NaiveTime, NaiveTime)) -> Vec<(u32, u32)> {
+    let (start, end) = (
+        start.num_seconds_from_midnight() / 60,
+        end.num_seconds_from_midnight() / 60,
+    );
+    if start < end {
+        vec![(start, end)]
+    } else {
+        vec![(start, 24 * 60), (0, end)]
+    }
+}
+
 pub type ResultE<T> = std::result::Result<T, Box<dyn Error>>;
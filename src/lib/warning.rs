@@ -0,0 +1,61 @@
+// Warnings before blocks. With block-warning.minutes set, --execute warns that many minutes before
+// a block starts, so work in web apps about to be blocked can be saved first. The warning is
+// logged, sent as a notification (see notifier.rs), and passed to block-warning.command if one is
+// set. The start of the last block warned about is kept in last-warning in the state dir, so each
+// block is warned about once.
+use chrono::{Duration, NaiveDateTime};
+use std::{fs, process::Command};
+use tracing::{info, warn};
+
+use super::{
+    config::{self, Config},
+    notifier::{self, Notification},
+    skips, ResultE,
+};
+
+const LAST_WARNING_FILE: &str = "last-warning";
+
+pub fn warn_before_next_block(config: &Config, now: NaiveDateTime) -> ResultE<()> {
+    let lead_time = Duration::minutes(config.block_warning.minutes as i64);
+    if lead_time <= Duration::zero() {
+        return Ok(());
+    }
+    let Some((block_start, block_end)) = skips::next_block(config, now)? else { return Ok(()) };
+    if block_start - now > lead_time {
+        return Ok(());
+    }
+
+    let last_warning_path = config::state_dir()?.join(LAST_WARNING_FILE);
+    let block_start_string = block_start.format("%Y-%m-%dT%H:%M").to_string();
+    if fs::read_to_string(&last_warning_path).ok().as_deref() == Some(&block_start_string) {
+        return Ok(());
+    }
+    fs::create_dir_all(config::state_dir()?)?;
+    fs::write(&last_warning_path, &block_start_string)?;
+
+    let message = format!(
+        "A block starts at {} and lasts until {}, save your work in any blocked sites",
+        block_start.format("%H:%M"),
+        block_end.format("%H:%M")
+    );
+    info!("{}", message);
+    notifier::notify(
+        &config.notifications,
+        &Notification::new("Block starting soon", &message),
+    );
+
+    if let Some(command) = &config.block_warning.command {
+        // not waited for, a slow command mustn't hold up the agent
+        let spawned = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("ASCRS_WARNING", &message)
+            .env("ASCRS_BLOCK_START", block_start.format("%Y-%m-%dT%H:%M").to_string())
+            .env("ASCRS_BLOCK_END", block_end.format("%Y-%m-%dT%H:%M").to_string())
+            .spawn();
+        if let Err(e) = spawned {
+            warn!("failed to run block-warning.command: {}", e);
+        }
+    }
+    Ok(())
+}
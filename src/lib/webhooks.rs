@@ -0,0 +1,321 @@
+// Webhooks. With webhooks.url set, each block event (started, ended, failed, skipped) is POSTed to
+// it as json, eg for a localhost dashboard. Events go through an outbox, webhook-outbox.json in the
+// state dir, so they survive the endpoint or the network being down: each event is sent in order,
+// and one that fails stays in the outbox and is retried by later runs (--execute runs every 30s)
+// with exponential backoff, until webhooks.max-attempts is reached and it's dropped.
+use chrono::{Duration, Local, NaiveDateTime};
+use fs2::FileExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+use tracing::{debug, warn};
+
+use super::{config, ResultE};
+
+const OUTBOX_FILE: &str = "webhook-outbox.json";
+const OUTBOX_LOCK_FILE: &str = "webhook-outbox.lock";
+const TIMEOUT_SECS: u64 = 5;
+const FIRST_RETRY_SECS: i64 = 30;
+const MAX_RETRY_SECS: i64 = 60 * 60;
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Webhooks {
+    url: Option<String>,
+    max_attempts: u32,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        Self {
+            url: None,
+            max_attempts: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum EventKind {
+    #[serde(rename = "block-started")]
+    Started,
+    #[serde(rename = "block-ended")]
+    Ended,
+    #[serde(rename = "block-failed")]
+    Failed,
+    #[serde(rename = "block-skipped")]
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Event {
+    event: EventKind,
+    time: NaiveDateTime,
+    block_start: Option<NaiveDateTime>,
+    block_end: NaiveDateTime,
+    error: Option<String>,
+}
+
+impl Event {
+    pub fn new(
+        event: EventKind,
+        block_start: Option<NaiveDateTime>,
+        block_end: NaiveDateTime,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            event,
+            time: Local::now().naive_local(),
+            block_start,
+            block_end,
+            error,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutboxEntry {
+    event: Event,
+    attempts: u32,
+    next_attempt_at: NaiveDateTime,
+}
+
+// only plain http is supported, webhooks are meant for local services
+pub fn check_url(webhooks: &Webhooks) -> ResultE<()> {
+    match &webhooks.url {
+        Some(url) if !url.starts_with("http://") => Err(format!(
+            "webhooks.url: {} isn't an http:// url, https isn't supported",
+            url
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+// queues event and sends what's due from the outbox, logging rather than returning any error
+pub fn send(webhooks: &Webhooks, event: Event) {
+    let Some(url) = &webhooks.url else { return };
+    let result = with_outbox(|outbox| {
+        push(outbox, event);
+        flush(webhooks, url, outbox);
+    });
+    if let Err(e) = result {
+        warn!("webhooks: {}", e);
+    }
+}
+
+// queues event without sending anything, for flush_outbox to send later
+pub fn queue(webhooks: &Webhooks, event: Event) {
+    if webhooks.url.is_none() {
+        return;
+    }
+    if let Err(e) = with_outbox(|outbox| push(outbox, event)) {
+        warn!("webhooks: {}", e);
+    }
+}
+
+fn push(outbox: &mut Vec<OutboxEntry>, event: Event) {
+    outbox.push(OutboxEntry {
+        next_attempt_at: event.time,
+        event,
+        attempts: 0,
+    });
+}
+
+// retries the events in the outbox which are due
+pub fn flush_outbox(webhooks: &Webhooks) {
+    let Some(url) = &webhooks.url else { return };
+    if !outbox_path().is_ok_and(|path| path.exists()) {
+        return;
+    }
+    if let Err(e) = with_outbox(|outbox| flush(webhooks, url, outbox)) {
+        warn!("webhooks: {}", e);
+    }
+}
+
+fn flush(webhooks: &Webhooks, url: &str, outbox: &mut Vec<OutboxEntry>) {
+    let now = Local::now().naive_local();
+    while let Some(entry) = outbox.first_mut() {
+        if entry.next_attempt_at > now {
+            return;
+        }
+        let Err(e) = post(url, &entry.event) else {
+            debug!("webhooks: sent an event");
+            outbox.remove(0);
+            continue;
+        };
+
+        entry.attempts += 1;
+        if entry.attempts >= webhooks.max_attempts {
+            warn!(
+                "webhooks: dropping an event after {} failed attempts: {}",
+                entry.attempts, e
+            );
+            outbox.remove(0);
+            continue;
+        }
+        let backoff = (FIRST_RETRY_SECS << (entry.attempts - 1).min(16)).min(MAX_RETRY_SECS);
+        entry.next_attempt_at = now + Duration::seconds(backoff);
+        warn!(
+            "webhooks: failed to send an event, retrying in {}s: {}",
+            backoff, e
+        );
+        // later events wait, so they arrive in order
+        return;
+    }
+}
+
+fn post(url: &str, event: &Event) -> ResultE<()> {
+    ureq::post(url)
+        .timeout(std::time::Duration::from_secs(TIMEOUT_SECS))
+        .set("Content-Type", "application/json")
+        .send_string(&serde_json::to_string(event)?)?;
+    Ok(())
+}
+
+fn outbox_path() -> ResultE<PathBuf> {
+    Ok(config::state_dir()?.join(OUTBOX_FILE))
+}
+
+// runs f on the outbox, holding a lock so concurrent runs don't lose or duplicate events
+fn with_outbox(f: impl FnOnce(&mut Vec<OutboxEntry>)) -> ResultE<()> {
+    let state_dir = config::state_dir()?;
+    fs::create_dir_all(&state_dir)?;
+    let lock_file = fs::File::create(state_dir.join(OUTBOX_LOCK_FILE))?;
+    lock_file.lock_exclusive()?;
+
+    let path = outbox_path()?;
+    let mut outbox: Vec<OutboxEntry> = match path.exists() {
+        true => serde_json::from_str(&fs::read_to_string(&path)?)?,
+        false => vec![],
+    };
+    f(&mut outbox);
+    match outbox.is_empty() {
+        true if path.exists() => fs::remove_file(path)?,
+        true => (),
+        false => fs::write(path, serde_json::to_string_pretty(&outbox)?)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc::{self, Receiver},
+        thread,
+    };
+
+    // a local http server answering each request with the next of statuses, returning its url and
+    // the bodies it received
+    fn serve(statuses: Vec<u16>) -> (String, Receiver<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/events", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                tx.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    // a url nothing listens on
+    fn refused_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/events", listener.local_addr().unwrap())
+    }
+
+    fn webhooks(url: &str, max_attempts: u32) -> Webhooks {
+        Webhooks {
+            url: Some(url.to_string()),
+            max_attempts,
+        }
+    }
+
+    fn entry(kind: EventKind, attempts: u32) -> OutboxEntry {
+        let block_end = NaiveDateTime::parse_from_str("2024-08-01 10:00", "%Y-%m-%d %H:%M").unwrap();
+        let event = Event::new(kind, None, block_end, None);
+        OutboxEntry {
+            next_attempt_at: event.time,
+            event,
+            attempts,
+        }
+    }
+
+    #[test]
+    fn posts_event_and_empties_outbox() {
+        let (url, bodies) = serve(vec![200]);
+        let mut outbox = vec![entry(EventKind::Started, 0)];
+        flush(&webhooks(&url, 10), &url, &mut outbox);
+
+        assert!(outbox.is_empty());
+        let body = bodies.recv().unwrap();
+        assert_eq!(body["event"], "block-started");
+        assert_eq!(body["block_end"], "2024-08-01T10:00:00");
+        assert_eq!(body["block_start"], Value::Null);
+        assert_eq!(body["error"], Value::Null);
+    }
+
+    #[test]
+    fn keeps_failed_event_with_backoff() {
+        let (url, _bodies) = serve(vec![500]);
+        for url in [url, refused_url()] {
+            let mut outbox = vec![entry(EventKind::Failed, 0)];
+            let before = Local::now().naive_local();
+            flush(&webhooks(&url, 10), &url, &mut outbox);
+
+            assert_eq!(outbox.len(), 1);
+            assert_eq!(outbox[0].attempts, 1);
+            assert!(outbox[0].next_attempt_at >= before + Duration::seconds(FIRST_RETRY_SECS));
+        }
+    }
+
+    #[test]
+    fn drops_event_after_max_attempts() {
+        let url = refused_url();
+        let mut outbox = vec![entry(EventKind::Ended, 2)];
+        flush(&webhooks(&url, 3), &url, &mut outbox);
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn delivers_events_in_order() {
+        // the first attempt fails, holding back the second event
+        let (url, bodies) = serve(vec![500, 200, 200]);
+        let mut outbox = vec![entry(EventKind::Started, 0), entry(EventKind::Ended, 0)];
+        flush(&webhooks(&url, 10), &url, &mut outbox);
+        assert_eq!(outbox.len(), 2);
+
+        outbox[0].next_attempt_at = Local::now().naive_local();
+        flush(&webhooks(&url, 10), &url, &mut outbox);
+        assert!(outbox.is_empty());
+        let events = bodies.iter().take(3).map(|body| body["event"].clone()).collect::<Vec<_>>();
+        assert_eq!(events, ["block-started", "block-started", "block-ended"]);
+    }
+}
@@ -1,11 +1,11 @@
 use chrono::Duration;
-use clap::{arg, command, Arg, ArgGroup};
+use clap::{arg, command, Arg, ArgAction, ArgGroup};
 use main_error::MainError;
-use std::{env, fs, path::Path};
+use std::{fs, path::PathBuf};
 use rpassword;
 
 mod lib;
-use lib::config::{Config, self};
+use lib::config::{Config, ConfigFormat, self};
 
 fn main() -> Result<(), MainError> {
     let matches = command!()
@@ -16,8 +16,28 @@ fn main() -> Result<(), MainError> {
             arg!(-e --execute "If we are in currently in an active block, activates SelfControlApp until \
                 the block ends"),
 
-            arg!(-w --write_example_config "Writes an example configuration file to \
-                ~/.config/auto-selfcontrol-rs/config.aoml"),
+            Arg::new("write_example_config")
+                .help("Writes an example configuration file to \
+                    ~/.config/auto-selfcontrol-rs/config.<yaml|toml|json>, defaults to yaml. If a \
+                    config path is given with --config or $ASCRS_CONFIG, writes there instead")
+                .short('w')
+                .long("write_example_config")
+                .num_args(0..=1)
+                .value_name("FORMAT")
+                .default_missing_value("yaml")
+                .value_parser(["yaml", "toml", "json"]),
+
+            arg!(-c --check "Parses the config file, reporting errors with their line and column, and warns \
+                about suspicious schedules"),
+
+            arg!(-j --json_schema "Prints a JSON Schema of the config file, for editors to validate and \
+                complete config.yaml"),
+
+            arg!(--print_config "Prints the config after merging included files, conf.d/ and the \
+                override section for this host"),
+
+            arg!(--migrate_config "Rewrites an outdated config file in the current config version, \
+                keeping the original as a .bak file. Comments are not preserved"),
 
             arg!(-p --set_keychain_password "Store the current MacOs user's password in keychain, which can then be used to automaticaly input into the SelfControl helper."),
 
@@ -27,6 +47,145 @@ fn main() -> Result<(), MainError> {
                 .long("start_self_control")
                 .num_args(1)
                 .value_parser(|mins: &str| mins.parse::<usize>()),
+
+            arg!(--pending_changes "Lists config changes waiting out the cooldown before they apply"),
+
+            Arg::new("cancel_pending_change")
+                .help("Cancels a pending config change, by the id listed by --pending_changes. The \
+                    schedule before it stays in force until the config file no longer makes the change")
+                .long("cancel_pending_change")
+                .num_args(1)
+                .value_name("ID")
+                .value_parser(clap::value_parser!(u32)),
+
+            arg!(--pause "Suppresses all scheduled blocks from --from until --to. Must be requested \
+                pause-notice-hours in advance")
+                .requires_all(["from", "to"]),
+
+            Arg::new("from")
+                .help("Start of the pause, history or stats: YYYY-MM-DD (start of that day) or \
+                    \"YYYY-MM-DD HH:MM\"")
+                .long("from")
+                .num_args(1)
+                .value_name("DATE")
+                .requires("date_range_commands"),
+
+            Arg::new("to")
+                .help("End of the pause, history or stats: YYYY-MM-DD (end of that day) or \
+                    \"YYYY-MM-DD HH:MM\"")
+                .long("to")
+                .num_args(1)
+                .value_name("DATE")
+                .requires("date_range_commands"),
+
+            arg!(--pauses "Lists upcoming and current pauses"),
+
+            arg!(--history "Prints the recorded history of --execute runs and block starts as json \
+                lines, from --from (default a week ago) until --to (default now)"),
+
+            Arg::new("stats")
+                .help("Shows blocked hours per day and week, adherence to the schedule, streaks \
+                    and helper cancels, from --from (default a week ago) until --to (default now). \
+                    As a table, or as json with --stats json")
+                .long("stats")
+                .num_args(0..=1)
+                .value_name("FORMAT")
+                .default_missing_value("table")
+                .value_parser(["table", "json"]),
+
+            Arg::new("report")
+                .help("Writes a report on a week's blocks, how much of them was enforced, and the \
+                    blocks missed with the SelfControl errors during them. Needs --week")
+                .long("report")
+                .num_args(1)
+                .value_name("FORMAT")
+                .value_parser(["markdown", "html", "csv"])
+                .requires("week"),
+
+            Arg::new("week")
+                .help("The week to report on: the one containing DATE (YYYY-MM-DD), default this \
+                    week")
+                .long("week")
+                .num_args(0..=1)
+                .value_name("DATE")
+                .requires("report"),
+
+            Arg::new("output")
+                .help("Where to write the report, default report-<week>.<md|html|csv> in the \
+                    current directory")
+                .long("output")
+                .num_args(1)
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf))
+                .requires("report"),
+
+            Arg::new("cancel_pause")
+                .help("Cancels a pause, by the id listed by --pauses")
+                .long("cancel_pause")
+                .num_args(1)
+                .value_name("ID")
+                .value_parser(clap::value_parser!(u32)),
+
+            arg!(--skip_next "Uses up one of this week's skips (skips-per-week) to cancel the next \
+                block before it starts"),
+
+            arg!(--status "Shows the active and next block, pauses and the skips left this week"),
+
+            Arg::new("bar")
+                .help("With --status, prints a line of status for a status bar instead: json for a \
+                    waybar custom module or an i3blocks blocklet, or a SwiftBar/xbar plugin's output")
+                .long("bar")
+                .num_args(1)
+                .value_name("FORMAT")
+                .value_parser(["waybar", "i3blocks", "swiftbar", "xbar"])
+                .requires("status"),
+
+            arg!(--daemon "Runs until stopped, activating SelfControl as blocks start instead of being \
+                run every 30 seconds. Installed by --deploy with daemon: true in the config"),
+
+            arg!(--serve_metrics "Serves Prometheus metrics on http://<metrics.listen>/metrics until \
+                stopped"),
+
+            Arg::new("generate_signing_key")
+                .help("For an accountability partner: writes a new private key to PATH, and prints \
+                    the public key to set as partner-public-key in the config")
+                .long("generate_signing_key")
+                .num_args(1)
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf)),
+
+            Arg::new("sign_config")
+                .help("For an accountability partner: signs the config with the private key at \
+                    PATH, allowing it to reduce blocked time")
+                .long("sign_config")
+                .num_args(1)
+                .value_name("PATH")
+                .value_parser(clap::value_parser!(PathBuf)),
+
+            Arg::new("config")
+                .help("Path to the config file to use, overriding $ASCRS_CONFIG and the default \
+                    $XDG_CONFIG_HOME/auto-selfcontrol-rs/ or ~/.config/auto-selfcontrol-rs/")
+                .long("config")
+                .num_args(1)
+                .value_name("PATH")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf)),
+
+            Arg::new("verbose")
+                .help("Logs more, -v for debug and -vv for trace. Logs go to stderr and \
+                    ~/.local/state/auto-selfcontrol-rs/ascrs.log")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count)
+                .global(true),
+
+            Arg::new("quiet")
+                .help("Logs less, -q for warnings and errors only and -qq for errors only")
+                .short('q')
+                .long("quiet")
+                .action(ArgAction::Count)
+                .global(true)
+                .conflicts_with("verbose"),
         ])
         .group(
             ArgGroup::new("commands")
@@ -35,29 +194,166 @@ fn main() -> Result<(), MainError> {
                     "execute",
                     "write_example_config",
                     "mins",
+                    "check",
+                    "json_schema",
+                    "print_config",
+                    "migrate_config",
+                    "pending_changes",
+                    "cancel_pending_change",
+                    "pause",
+                    "pauses",
+                    "history",
+                    "stats",
+                    "report",
+                    "cancel_pause",
+                    "skip_next",
+                    "status",
+                    "daemon",
+                    "serve_metrics",
+                    "generate_signing_key",
+                    "sign_config",
                     "set_keychain_password"
                 ])
                 .multiple(false)
                 .required(true)
         )
+        .group(ArgGroup::new("date_range_commands").args(["pause", "history", "stats"]))
         .get_matches();
 
-    let home_dir = env::var_os("HOME").ok_or_else(|| "HOME environment variable not set")?;
-    let home_dir = Path::new(&home_dir);
-    let config_dir = home_dir.join(".config").join("auto-selfcontrol-rs/");
+    lib::init_logging(matches.get_count("verbose"), matches.get_count("quiet"))?;
 
+    let explicit_config_path = config::explicit_config_path(matches.get_one::<PathBuf>("config"));
+    let config_dir = config::config_dir()?;
     fs::create_dir_all(&config_dir)?;
-    let config_path = config_dir.join("config.yaml");
 
-    if matches.get_flag("write_example_config") {
-        let example_config = config::build_example_config();
-        fs::write(&config_path, example_config)?;
+    if let Some(format) = matches.get_one::<String>("write_example_config") {
+        let config_path = explicit_config_path
+            .unwrap_or_else(|| config_dir.join(format!("config.{}", format)));
+        let format = ConfigFormat::from_path(&config_path)?;
+        fs::write(&config_path, config::build_example_config(format))?;
+        return Ok(());
+    }
+
+    if let Some(private_key_path) = matches.get_one::<PathBuf>("generate_signing_key") {
+        let public_key = lib::generate_signing_key(private_key_path)?;
+        println!("partner-public-key: {}", public_key);
+        return Ok(());
+    }
+
+    let config_path = match explicit_config_path {
+        Some(config_path) => config_path,
+        None => config::find_config_file(&config_dir)?,
+    };
+
+    if matches.get_flag("json_schema") {
+        println!("{}", config::build_json_schema()?);
+        return Ok(());
+    }
+
+    if matches.get_flag("check") {
+        lib::check(&config_path)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("print_config") {
+        println!("{}", config::build_merged_config(&config_path)?);
+        return Ok(());
+    }
+
+    if matches.get_flag("pending_changes") {
+        lib::print_pending_changes(&config_path)?;
+        return Ok(());
+    }
+
+    if let Some(id) = matches.get_one::<u32>("cancel_pending_change") {
+        lib::cancel_pending_change(&config_path, *id)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("pause") {
+        let from = matches.get_one::<String>("from").ok_or("--from is required")?;
+        let to = matches.get_one::<String>("to").ok_or("--to is required")?;
+        lib::pause(&config_path, from, to)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("history") {
+        let from = matches.get_one::<String>("from");
+        let to = matches.get_one::<String>("to");
+        lib::print_history(from.map(String::as_str), to.map(String::as_str))?;
+        return Ok(());
+    }
+
+    if let Some(format) = matches.get_one::<String>("stats") {
+        let from = matches.get_one::<String>("from");
+        let to = matches.get_one::<String>("to");
+        lib::print_stats(&config_path, from.map(String::as_str), to.map(String::as_str), format == "json")?;
+        return Ok(());
+    }
+
+    if let Some(format) = matches.get_one::<String>("report") {
+        let week_of = matches.get_one::<String>("week");
+        let output = matches.get_one::<PathBuf>("output");
+        lib::write_report(&config_path, format, week_of.map(String::as_str), output.map(PathBuf::as_path))?;
+        return Ok(());
+    }
+
+    if matches.get_flag("pauses") {
+        lib::print_pauses()?;
+        return Ok(());
+    }
+
+    if let Some(id) = matches.get_one::<u32>("cancel_pause") {
+        lib::cancel_pause(*id)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("skip_next") {
+        lib::skip_next(&config_path)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("daemon") {
+        lib::run_daemon(&config_path)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("serve_metrics") {
+        lib::serve_metrics(&config_path)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("status") {
+        match matches.get_one::<String>("bar") {
+            Some(format) => lib::print_status_bar(&config_path, format)?,
+            None => lib::print_status(&config_path)?,
+        }
+        return Ok(());
+    }
+
+    if let Some(private_key_path) = matches.get_one::<PathBuf>("sign_config") {
+        lib::sign_config(&config_path, private_key_path)?;
+        return Ok(());
+    }
+
+    if matches.get_flag("migrate_config") {
+        match config::migrate_config_file(&config_path)? {
+            Some(version) => println!(
+                "Migrated {} from version {}, the original was kept as a .bak file",
+                config_path.display(),
+                version
+            ),
+            None => println!("{} is already up to date", config_path.display()),
+        }
         return Ok(());
     }
 
-    let config = Config::build(&config_path)?;
+    let config = match matches.get_flag("execute") {
+        true => lib::load_config_for_execute(&config_path)?,
+        false => Config::build(&config_path)?,
+    };
     if matches.get_flag("deploy") {
-        lib::deploy(&config)?;
+        lib::deploy(&config, &config_path)?;
     }
     if matches.get_flag("execute") {
         lib::execute(&config)?;